@@ -0,0 +1,74 @@
+//! Output formats of the generated positions.
+//!
+//! Text: one position per line as `<fen> | <score> | <result>`, where the score is in centipawns
+//! from white's view and the result is `1.0`, `0.5` or `0.0` for a white win, draw or black win.
+//!
+//! Binary: 32 byte little endian records of
+//! - `u64` occupancy, with a1 as the lowest bit
+//! - `[u8; 16]` pieces in occupancy order, two per byte starting from the low nibble, each as
+//!   `color << 3 | piece` with white as 0 and pawn to king as 0 to 5
+//! - `i16` score from white's view
+//! - `u8` result, 2 for a white win, 1 for a draw and 0 for a black win
+//! - `u8` side to move, 0 for white
+//! - `[u8; 4]` reserved as zeros
+
+use std::io::{self, Write};
+
+use chess::{Board, Color};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    BlackWin,
+    Draw,
+    WhiteWin,
+}
+
+pub struct Position {
+    pub board: Board,
+    pub fen: String,
+    /// Centipawns from white's view
+    pub score: i16,
+}
+
+impl Format {
+    pub fn write<W: Write>(self, w: &mut W, pos: &Position, result: GameResult) -> io::Result<()> {
+        match self {
+            Self::Text => {
+                let result = match result {
+                    GameResult::BlackWin => "0.0",
+                    GameResult::Draw => "0.5",
+                    GameResult::WhiteWin => "1.0",
+                };
+
+                writeln!(w, "{} | {} | {result}", pos.fen, pos.score)
+            },
+            Self::Binary => w.write_all(&encode(pos, result)),
+        }
+    }
+}
+
+fn encode(pos: &Position, result: GameResult) -> [u8; 32] {
+    let mut record = [0; 32];
+    let occupancy = *pos.board.combined();
+    record[0..8].copy_from_slice(&occupancy.0.to_le_bytes());
+
+    for (i, sq) in occupancy.enumerate() {
+        // SAFETY: only squares with things on it are checked
+        let piece = unsafe { pos.board.piece_on(sq).unwrap_unchecked() };
+        let color = unsafe { pos.board.color_on(sq).unwrap_unchecked() };
+
+        let nibble = (color.to_index() << 3 | piece.to_index()) as u8;
+        record[8 + i / 2] |= nibble << (4 * (i % 2));
+    }
+
+    record[24..26].copy_from_slice(&pos.score.to_le_bytes());
+    record[26] = result as u8;
+    record[27] = (pos.board.side_to_move() == Color::Black) as u8;
+    record
+}
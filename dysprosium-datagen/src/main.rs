@@ -0,0 +1,234 @@
+//! Self-play generator of training data for the evaluation.
+//!
+//! Every thread plays its own games from a few random opening plies, searching each move to a
+//! fixed number of nodes, and sends the positions of finished games to the main thread to be
+//! written out. See [`format`] for the output formats.
+
+use std::{fs::File, io::{BufWriter, Write}, sync::{atomic::*, mpsc, Arc}, time::{Duration, Instant}};
+
+use chess::{BoardStatus, Color, MoveGen};
+use dysprosium::{Engine, Game, SearchLimits};
+use format::{Format, GameResult, Position};
+
+mod format;
+
+const MB: usize = 1024 * 1024;
+
+/// Games are won once the score stays above this for `WIN_PLIES` plies
+const WIN_SCORE: i16 = 2000;
+const WIN_PLIES: usize = 6;
+/// Games are drawn once the score stays within this for `DRAW_PLIES` plies after `DRAW_AFTER`
+const DRAW_SCORE: i16 = 10;
+const DRAW_PLIES: usize = 12;
+const DRAW_AFTER: usize = 60;
+const MAX_PLIES: usize = 500;
+
+const USAGE: &str = "\
+usage: dysprosium-datagen [options]
+    --output <path>         file to append positions to (default: data.txt)
+    --format <text|binary>  output format (default: text)
+    --threads <n>           concurrent games (default: 1)
+    --games <n>             stop after this many games (default: run forever)
+    --nodes <n>             nodes to search per move (default: 5000)
+    --random-plies <n>      random plies played from the start position (default: 8)
+    --hash <mb>             hash size per game (default: 16)
+    --keep-check            also keep positions with the side to move in check
+    --keep-tactical         also keep positions where the best move is a capture or promotion";
+
+struct Options {
+    output: String,
+    format: Format,
+    threads: usize,
+    games: Option<usize>,
+    nodes: usize,
+    random_plies: usize,
+    hash_mb: usize,
+    keep_check: bool,
+    keep_tactical: bool,
+}
+
+impl Options {
+    fn from_args() -> Result<Self, String> {
+        let mut opts = Self {
+            output: "data.txt".to_string(),
+            format: Format::Text,
+            threads: 1,
+            games: None,
+            nodes: 5000,
+            random_plies: 8,
+            hash_mb: 16,
+            keep_check: false,
+            keep_tactical: false,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for `{arg}`"));
+            let number = |v: String| v.parse::<usize>().map_err(|e| format!("invalid number `{v}`: {e}"));
+
+            match arg.as_str() {
+                "--output" => opts.output = value()?,
+                "--format" => opts.format = match value()?.as_str() {
+                    "text" => Format::Text,
+                    "binary" => Format::Binary,
+                    f => return Err(format!("unknown format `{f}`")),
+                },
+                "--threads" => opts.threads = number(value()?)?.max(1),
+                "--games" => opts.games = Some(number(value()?)?),
+                "--nodes" => opts.nodes = number(value()?)?,
+                "--random-plies" => opts.random_plies = number(value()?)?,
+                "--hash" => opts.hash_mb = number(value()?)?.max(1),
+                "--keep-check" => opts.keep_check = true,
+                "--keep-tactical" => opts.keep_tactical = true,
+                _ => return Err(format!("unknown option `{arg}`")),
+            }
+        }
+
+        Ok(opts)
+    }
+}
+
+fn main() {
+    let opts = match Options::from_args() {
+        Ok(opts) => Arc::new(opts),
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            std::process::exit(1);
+        },
+    };
+
+    let file = File::options().create(true).append(true).open(&opts.output).unwrap();
+    let mut out = BufWriter::new(file);
+
+    let started = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = mpsc::channel();
+
+    for _ in 0..opts.threads {
+        let opts = Arc::clone(&opts);
+        let started = Arc::clone(&started);
+        let tx = tx.clone();
+
+        std::thread::spawn(move || {
+            let mut engine = Engine::new(Game::default(), opts.hash_mb * MB);
+            engine.allow_for(Duration::MAX);
+            engine.set_limits(SearchLimits { nodes: Some(opts.nodes), ..Default::default() });
+
+            while opts.games.is_none_or(|g| started.fetch_add(1, Ordering::Relaxed) < g) {
+                engine.new_game();
+                if tx.send(play_game(&engine, &opts)).is_err() { break };
+            }
+        });
+    }
+    drop(tx);
+
+    let start = Instant::now();
+    let (mut games, mut positions) = (0, 0);
+    let mut results = [0; 3];
+
+    for (game, result) in rx {
+        for pos in game.iter() {
+            opts.format.write(&mut out, pos, result).unwrap();
+        }
+
+        games += 1;
+        positions += game.len();
+        results[result as usize] += 1;
+
+        if games % 100 == 0 {
+            out.flush().unwrap();
+
+            let rate = positions as f64 / start.elapsed().as_secs_f64();
+            println!(
+                "{games} games, {positions} positions ({rate:.0} pos/s), +{} ={} -{}",
+                results[GameResult::WhiteWin as usize],
+                results[GameResult::Draw as usize],
+                results[GameResult::BlackWin as usize],
+            );
+        }
+    }
+
+    out.flush().unwrap();
+    println!("done: {games} games, {positions} positions");
+}
+
+/// Play a game against itself and return the kept positions with the result
+fn play_game(engine: &Engine, opts: &Options) -> (Vec<Position>, GameResult) {
+    let mut game = random_opening(opts.random_plies);
+    let mut positions = Vec::new();
+    let (mut win_plies, mut draw_plies) = (0, 0);
+
+    for ply in 0.. {
+        if game.can_declare_draw() || ply >= MAX_PLIES {
+            return (positions, GameResult::Draw);
+        }
+
+        match game.board().status() {
+            BoardStatus::Ongoing => {},
+            BoardStatus::Stalemate => return (positions, GameResult::Draw),
+            BoardStatus::Checkmate => return (positions, winner(!game.board().side_to_move())),
+        }
+
+        *engine.game.write() = game.clone();
+        let (best, eval, _) = engine.best_move(|_, _| true);
+
+        let score = if game.board().side_to_move() == Color::White { eval } else { -eval };
+        let in_check = game.board().checkers().0 != 0;
+        let tactical = !game.is_quiet(best);
+
+        if !eval.is_mate() && (opts.keep_check || !in_check) && (opts.keep_tactical || !tactical) {
+            positions.push(Position {
+                board: *game.board(),
+                fen: game.to_fen(),
+                score: score.0,
+            });
+        }
+
+        // adjudicate on scores both sides agree with
+        if score.is_mate() || score.0.abs() >= WIN_SCORE {
+            win_plies += 1;
+            if win_plies >= WIN_PLIES {
+                return (positions, winner(if score.0 > 0 { Color::White } else { Color::Black }));
+            }
+        } else {
+            win_plies = 0;
+        }
+
+        if ply >= DRAW_AFTER && score.0.abs() <= DRAW_SCORE {
+            draw_plies += 1;
+            if draw_plies >= DRAW_PLIES {
+                return (positions, GameResult::Draw);
+            }
+        } else {
+            draw_plies = 0;
+        }
+
+        game = game.make_move(best);
+    }
+
+    unreachable!()
+}
+
+/// Play random moves from the start position, retrying if the game ends on the way
+fn random_opening(plies: usize) -> Game {
+    'retry: loop {
+        let mut game = Game::default();
+
+        for _ in 0..plies {
+            let moves = MoveGen::new_legal(game.board()).collect::<Vec<_>>();
+            if moves.is_empty() { continue 'retry };
+
+            game = game.make_move(moves[fastrand::usize(..moves.len())]);
+        }
+
+        if MoveGen::new_legal(game.board()).len() != 0 {
+            return game;
+        }
+    }
+}
+
+fn winner(color: Color) -> GameResult {
+    match color {
+        Color::White => GameResult::WhiteWin,
+        Color::Black => GameResult::BlackWin,
+    }
+}
@@ -1,6 +1,6 @@
 use std::io::Read;
 
-use chess::{ChessMove, Color, Piece, Square};
+use chess::{ChessMove, Color};
 // use reqwest::{header, Client, Request, Response, Result as ReqResure
 use serde::Deserialize;
 use serde_json::from_str;
@@ -23,7 +23,7 @@ pub struct Error<'a> {
 #[serde(rename_all = "camelCase")]
 #[serde(rename_all_fields = "camelCase")]
 pub enum GameEvent<'a> {
-    GameFull { initial_fen: &'a str, state: GameState<'a> },
+    GameFull { initial_fen: &'a str, white: Player<'a>, black: Player<'a>, created_at: u64, state: GameState<'a> },
     GameState { #[serde(flatten)] state: GameState<'a> },
     ChatLine {},
     OpponentGone {},
@@ -36,6 +36,10 @@ pub struct GameState<'a> {
     pub winc: usize,
     pub btime: usize,
     pub binc: usize,
+    #[serde(default)]
+    pub status: &'a str,
+    #[serde(default)]
+    pub winner: Option<ColorNt>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -113,7 +117,7 @@ pub struct ColorNt(pub Color);
 
 // impl<'a> From<&'a str> for ChessMoveNt {
 //     fn from(value: &'a str) -> Self {
-//         Self(move_from_uci(value))
+//         Self(dysprosium::parse_uci_move(value).unwrap())
 //     }
 // }
 
@@ -238,24 +242,6 @@ impl<R: Read> JsonStreamIter<R> {
     }
 }
 
-pub fn move_from_uci(m: &str) -> ChessMove {
-    let src = &m[0..2];
-    let src = Square::new(((src.as_bytes()[1] - b'1') << 3) + (src.as_bytes()[0] - b'a'));
-
-    let dst = &m[2..4];
-    let dst = Square::new(((dst.as_bytes()[1] - b'1') << 3) + (dst.as_bytes()[0] - b'a'));
-
-    let piece = m.as_bytes().get(4).and_then(|p| match p {
-        b'n' => Some(Piece::Knight),
-        b'b' => Some(Piece::Bishop),
-        b'q' => Some(Piece::Queen),
-        b'r' => Some(Piece::Rook),
-        _ => None,
-    });
-
-    ChessMove::new(src, dst, piece)
-}
-
 fn success(status: u16) -> bool {
     (200..=299).contains(&status)
 }
@@ -1,4 +1,4 @@
-use std::{path::Path, sync::{Arc, RwLockReadGuard}};
+use std::{path::{Path, PathBuf}, sync::{Arc, RwLockReadGuard}};
 
 use notify::{PollWatcher, RecursiveMode, Watcher};
 use serde::Deserialize;
@@ -24,6 +24,18 @@ pub struct Config {
     pub threads_per_game: usize,
     #[serde(default)]
     pub max_games: Option<usize>,
+    /// Think on the opponent's time
+    #[serde(default = "_false")]
+    pub ponder: bool,
+    /// Polyglot opening book to play from before searching
+    #[serde(default)]
+    pub book: Option<PathBuf>,
+    /// Play the most weighted book move instead of picking one by weight
+    #[serde(default = "_false")]
+    pub book_best: bool,
+    /// File to append the PGN of every finished game to
+    #[serde(default)]
+    pub save_pgn: Option<PathBuf>,
 }
 
 fn _true() -> bool { true }
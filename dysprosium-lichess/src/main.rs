@@ -1,11 +1,10 @@
 #![warn(clippy::future_not_send)]
 
-use core::str::FromStr;
-use std::sync::{atomic::*, Arc, RwLock};
-use api::{move_from_uci, Challenge, Direction, Event, GameEvent, GameState, LichessApi, Player, Variant};
-use chess::{Board, BoardStatus, Color};
+use std::{collections::HashMap, fs::File, io::Write, path::Path, sync::{atomic::*, Arc, RwLock}, thread::JoinHandle};
+use api::{Challenge, Direction, Event, GameEvent, GameState, LichessApi, Player, Variant};
+use chess::{BoardStatus, ChessMove, Color};
 use config::Config;
-use dysprosium::Game;
+use dysprosium::{book::{Book, BookSelection}, Eval, Game, PgnGame, PgnMove};
 
 mod api;
 mod config;
@@ -55,7 +54,7 @@ impl LichessClient {
                 }
             },
             Event::GameStart { game: api::Game { id, color, fen, opponent, .. } } => {
-                let game = dysprosium::Game::from_str(fen).unwrap();
+                let game = Game::from_fen(fen).unwrap();
                 self.active_games.fetch_add(1, Ordering::Relaxed);
 
                 info!("started a game with `{}` (id: `{id}`, fen: `{fen}`)", opponent.username.unwrap());
@@ -72,76 +71,234 @@ impl LichessClient {
     }
 
     fn play_game(self: Arc<Self>, game_id: String, game: dysprosium::Game, color: Color) {
-        let mut engine = dysprosium::Engine::new(game, 64 * 1024 * 1024);
-        engine.start_smp(self.config().threads_per_game - 1);
-
-        self.api.listen_game(&game_id, |event| match event {
-            GameEvent::GameFull { initial_fen, state } => {
-                let mut game = engine.game.write();
-                *game = Game::new(Board::from_str(initial_fen).unwrap_or_default());
-                for m in state.moves.split_whitespace() {
-                    *game = game.make_move(move_from_uci(m));
-                }
+        let mut engine = Arc::new(dysprosium::Engine::new(game.clone(), 64 * 1024 * 1024));
+        Arc::get_mut(&mut engine).unwrap().start_smp(self.config().threads_per_game - 1);
 
-                if game.board().side_to_move() == color {
-                    drop(game);
-                    self.play(&game_id, color, state, &mut engine);
-                }
-            },
-            GameEvent::GameState { state } => {
-                let mut game = engine.game.write();
-                if let Some(m) = state.moves.split_whitespace().last() {
-                    *game = game.make_move(move_from_uci(m));
-                }
+        let book = self.config().book.as_ref().and_then(|path| Book::open(path)
+            .inspect_err(|e| info!("failed to open book `{}`: {e}", path.display()))
+            .ok());
 
-                if game.board().side_to_move() == color {
-                    drop(game);
-                    self.play(&game_id, color, state, &mut engine);
-                }
-            },
-            _ => dbg!("{event:?}"),
+        let mut initial = game;
+        let mut ponder_move = None;
+        let mut ponder: Option<Ponder> = None;
+        let mut pgn = PgnGame::new(initial.clone());
+        // our evaluations by the ply of the move
+        let mut evals = HashMap::new();
+
+        self.api.listen_game(&game_id, |event| {
+            let state = match event {
+                GameEvent::GameFull { initial_fen, white, black, created_at, state } => {
+                    initial = Game::from_fen(initial_fen).unwrap_or_default();
+
+                    pgn = PgnGame::new(initial.clone());
+                    pgn.set_tag("Event", "Lichess game");
+                    pgn.set_tag("Site", format!("https://lichess.org/{game_id}"));
+                    pgn.set_tag("Date", pgn_date(created_at));
+                    pgn.set_tag("White", white.name.unwrap_or("?"));
+                    pgn.set_tag("Black", black.name.unwrap_or("?"));
+                    state
+                },
+                GameEvent::GameState { state } => state,
+                _ => {
+                    dbg!("{event:?}");
+                    return;
+                },
+            };
+
+            let mut game = initial.clone();
+            let mut last = None;
+            pgn.moves.clear();
+            for m in state.moves.split_whitespace() {
+                let m = match game.parse_uci_move(m) {
+                    Ok(m) => m,
+                    Err(e) => {
+                        error!("{e} in game `{game_id}`");
+                        return;
+                    },
+                };
+
+                game = game.make_move(m);
+                last = Some(m);
+                pgn.moves.push(match evals.get(&pgn.moves.len()) {
+                    Some(&(eval, depth)) => PgnMove::with_eval(m, eval, depth),
+                    None => PgnMove::new(m),
+                });
+            }
+            pgn.set_result(result(&state));
+
+            if game.board().side_to_move() == color {
+                let searched = ponder.take().and_then(|ponder| if last == Some(ponder.expected) {
+                    info!("ponder hit on {}", ponder.expected);
+                    engine.ponderhit();
+                    ponder.search.join().ok()
+                } else {
+                    engine.stop();
+                    let _ = ponder.search.join();
+                    None
+                });
+
+                ponder_move = if searched.is_none() && self.play_book(&game_id, &game, book.as_ref()) {
+                    None
+                } else {
+                    self.play(&game_id, color, &state, &game, &mut engine, searched).and_then(|(next, eval, depth)| {
+                        evals.insert(pgn.moves.len(), (eval, depth));
+                        engine.find_pv(next, 2).get(1).copied()
+                    })
+                };
+            } else if let Some(expected) = ponder_move.take().filter(|_| self.config().ponder) {
+                ponder = self.ponder(color, &state, &game, expected, &mut engine);
+            }
         });
 
+        if let Some(ponder) = ponder {
+            engine.stop();
+            let _ = ponder.search.join();
+        }
+
         info!("stream ended (id: `{}`)", game_id);
-    }
 
-    fn play(&self, game_id: &str, color: Color, state: GameState<'_>, engine: &mut dysprosium::Engine) {
-        {
-            let game = engine.game.read();
-            if game.can_declare_draw() || game.board().status() != BoardStatus::Ongoing {
-                return;
-            }
+        let save_pgn = self.config().save_pgn.clone();
+        if let Some(path) = save_pgn.filter(|_| !pgn.moves.is_empty()) {
+            save_game(&path, &pgn);
         }
+    }
 
-        engine.time_control(None, match color {
-            Color::White => dysprosium::TimeControl {
-                time_left: state.wtime,
-                time_incr: state.winc,
-            },
-            Color::Black => dysprosium::TimeControl {
-                time_left: state.btime,
-                time_incr: state.binc,
-            },
-        });
+    /// Send a move from the book if it has one for this position.
+    fn play_book(&self, game_id: &str, game: &Game, book: Option<&Book>) -> bool {
+        let selection = if self.config().book_best { BookSelection::Best } else { BookSelection::Weighted };
 
-        let (next, _, _) = engine.best_move(|engine, (best, eval, depth)| {
-            let nodes = engine.nodes();
-            let time = engine.elapsed().as_secs_f64();
-
-            info!(
-                "searched {nodes} nodes at {depth}-ply deep in {time:.2}s ({:.2} MN/s), PV: {} ({eval})",
-                nodes as f64 / time / 1_000_000.0,
-                engine.find_pv(best, 20).into_iter()
-                .map(|m| m.to_string())
-                .collect::<Vec<_>>()
-                .join(" "),
-            );
+        if let Some(next) = book.and_then(|book| book.select(game, selection)) {
+            info!("playing {} from the book", game.to_san(next));
+            self.api.send_move(game_id, next);
             true
+        } else {
+            false
+        }
+    }
+
+    /// Send our move, searching for it unless a ponder search already found it. Returns the
+    /// search result.
+    fn play(
+        &self,
+        game_id: &str,
+        color: Color,
+        state: &GameState<'_>,
+        game: &Game,
+        engine: &mut Arc<dysprosium::Engine>,
+        searched: Option<(ChessMove, Eval, usize)>,
+    ) -> Option<(ChessMove, Eval, usize)> {
+        if game.can_declare_draw() || game.board().status() != BoardStatus::Ongoing {
+            return None;
+        }
+
+        let searched = searched.unwrap_or_else(|| {
+            let engine = Arc::get_mut(engine).unwrap();
+            *engine.game.write() = game.clone();
+            engine.time_control(None, time_left(color, state));
+            engine.set_pondering(false);
+            engine.clear_stop();
+
+            engine.best_move(log_search)
         });
-        self.api.send_move(game_id, next);
+        self.api.send_move(game_id, searched.0);
+
+        Some(searched)
+    }
+
+    /// Start searching the position after `expected` on another thread while the opponent
+    /// thinks.
+    fn ponder(
+        &self,
+        color: Color,
+        state: &GameState<'_>,
+        game: &Game,
+        expected: ChessMove,
+        engine: &mut Arc<dysprosium::Engine>,
+    ) -> Option<Ponder> {
+        let game = game.make_move(expected);
+        if game.can_declare_draw() || game.board().status() != BoardStatus::Ongoing {
+            return None;
+        }
+
+        let e = Arc::get_mut(engine).unwrap();
+        *e.game.write() = game;
+        e.time_control(None, time_left(color, state));
+        e.set_pondering(true);
+        e.clear_stop();
+
+        let engine = Arc::clone(engine);
+        Some(Ponder {
+            expected,
+            search: std::thread::spawn(move || engine.best_move(log_search)),
+        })
+    }
+}
+
+/// A search running on the position after the move we expect the opponent to play
+struct Ponder {
+    expected: ChessMove,
+    search: JoinHandle<(ChessMove, Eval, usize)>,
+}
+
+fn time_left(color: Color, state: &GameState<'_>) -> dysprosium::TimeControl {
+    match color {
+        Color::White => dysprosium::TimeControl {
+            time_left: state.wtime,
+            time_incr: state.winc,
+        },
+        Color::Black => dysprosium::TimeControl {
+            time_left: state.btime,
+            time_incr: state.binc,
+        },
     }
 }
 
+/// The result of a game by its state, `*` while it is still going on or if it was aborted
+fn result(state: &GameState<'_>) -> &'static str {
+    match (state.winner.as_ref().map(|w| w.0), state.status) {
+        (Some(Color::White), _) => "1-0",
+        (Some(Color::Black), _) => "0-1",
+        (None, "" | "created" | "started" | "aborted" | "noStart") => "*",
+        (None, _) => "1/2-1/2",
+    }
+}
+
+/// The `YYYY.MM.DD` UTC date of a timestamp in milliseconds
+fn pgn_date(ms: u64) -> String {
+    // civil from days, shifted to start the year on the 1st of March
+    let days = ms / 86_400_000 + 719_468;
+    let (era, doe) = (days / 146_097, days % 146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + yoe + (month <= 2) as u64;
+    format!("{year}.{month:02}.{day:02}")
+}
+
+fn save_game(path: &Path, pgn: &PgnGame) {
+    let saved = File::options().create(true).append(true).open(path).and_then(|mut f| writeln!(f, "{pgn}"));
+
+    match saved {
+        Ok(()) => info!("saved game to `{}`", path.display()),
+        Err(e) => warn!("failed to save game to `{}`: {e}", path.display()),
+    }
+}
+
+fn log_search(engine: &dysprosium::Engine, (best, eval, depth): (ChessMove, Eval, usize)) -> bool {
+    let nodes = engine.nodes();
+    let time = engine.elapsed().as_secs_f64();
+
+    info!(
+        "searched {nodes} nodes at {depth}-ply deep in {time:.2}s ({:.2} MN/s), PV: {} ({eval})",
+        nodes as f64 / time / 1_000_000.0,
+        engine.game.read().to_san_line(&engine.find_pv(best, 20)),
+    );
+    true
+}
+
 fn main() {
     let api_key = std::fs::read_to_string("api_key.txt").unwrap().trim().to_string();
     Arc::new(LichessClient::new(LichessApi::new(api_key))).listen();
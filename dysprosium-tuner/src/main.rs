@@ -0,0 +1,226 @@
+//! Texel tuner for the PeSTO evaluation.
+//!
+//! Reads positions labelled with game results, fits the sigmoid scale `K` to the current
+//! parameters, then minimises the mean squared error between the results and the sigmoid of the
+//! evaluations with Adam. The tuned parameters are written out as a replacement for
+//! `src/eval_params.rs`.
+//!
+//! Every line of the data files is one position, either as EPD with a `c9 "1-0"` style result,
+//! as FEN followed by a `[1.0]` style result, or in the `fen | score | result` format of
+//! `dysprosium-datagen`.
+
+use std::{fs::File, io::{BufRead, BufReader}};
+
+use chess::Board;
+use dysprosium::{pesto_params, pesto_trace, Game, PestoTrace, PESTO_PARAMS};
+
+mod output;
+
+const USAGE: &str = "\
+usage: dysprosium-tuner [options] <data files...>
+    --output <path>  file to write the tuned parameters to (default: eval_params.rs)
+    --epochs <n>     number of gradient descent steps (default: 1000)
+    --lr <rate>      learning rate of Adam in centipawns (default: 1.0)
+    --k <k>          sigmoid scale, fitted to the data if not given";
+
+struct Options {
+    files: Vec<String>,
+    output: String,
+    epochs: usize,
+    lr: f64,
+    k: Option<f64>,
+}
+
+impl Options {
+    fn from_args() -> Result<Self, String> {
+        let mut opts = Self {
+            files: Vec::new(),
+            output: "eval_params.rs".to_string(),
+            epochs: 1000,
+            lr: 1.0,
+            k: None,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("missing value for `{arg}`"));
+
+            match arg.as_str() {
+                "--output" => opts.output = value()?,
+                "--epochs" => opts.epochs = value()?.parse().map_err(|e| format!("invalid epochs: {e}"))?,
+                "--lr" => opts.lr = value()?.parse().map_err(|e| format!("invalid learning rate: {e}"))?,
+                "--k" => opts.k = Some(value()?.parse().map_err(|e| format!("invalid k: {e}"))?),
+                _ if arg.starts_with("--") => return Err(format!("unknown option `{arg}`")),
+                _ => opts.files.push(arg),
+            }
+        }
+
+        if opts.files.is_empty() {
+            return Err("no data files given".to_string());
+        }
+
+        Ok(opts)
+    }
+}
+
+struct Entry {
+    trace: PestoTrace,
+    /// 1 for a white win, 0.5 for a draw and 0 for a black win
+    result: f64,
+}
+
+fn main() {
+    let opts = match Options::from_args() {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            std::process::exit(1);
+        },
+    };
+
+    let mut entries = Vec::new();
+    for path in opts.files.iter() {
+        let file = BufReader::new(File::open(path).unwrap());
+        let before = entries.len();
+
+        for line in file.lines() {
+            let line = line.unwrap();
+            if let Some((board, result)) = parse_line(&line) {
+                entries.push(Entry { trace: pesto_trace(&board), result });
+            }
+        }
+
+        println!("loaded {} positions from `{path}`", entries.len() - before);
+    }
+
+    let mut params = pesto_params().map(|p| p as f64);
+    let k = opts.k.unwrap_or_else(|| fit_k(&entries, &params));
+    println!("k = {k:.3}, error = {:.6}", error(&entries, &params, k));
+
+    let mut m = [0.0; PESTO_PARAMS];
+    let mut v = [0.0; PESTO_PARAMS];
+    const BETA1: f64 = 0.9;
+    const BETA2: f64 = 0.999;
+
+    for epoch in 1..=opts.epochs {
+        let grad = gradient(&entries, &params, k);
+
+        for i in 0..PESTO_PARAMS {
+            m[i] = BETA1 * m[i] + (1.0 - BETA1) * grad[i];
+            v[i] = BETA2 * v[i] + (1.0 - BETA2) * grad[i] * grad[i];
+
+            let m_hat = m[i] / (1.0 - BETA1.powi(epoch as i32));
+            let v_hat = v[i] / (1.0 - BETA2.powi(epoch as i32));
+            params[i] -= opts.lr * m_hat / (v_hat.sqrt() + 1e-8);
+        }
+
+        if epoch % 50 == 0 || epoch == opts.epochs {
+            println!("epoch {epoch}: error = {:.6}", error(&entries, &params, k));
+            output::write_params(&mut File::create(&opts.output).unwrap(), &params).unwrap();
+        }
+    }
+
+    println!("written to `{}`", opts.output);
+}
+
+fn parse_line(line: &str) -> Option<(Board, f64)> {
+    let (game, result) = if let Some((position, result)) = line.rsplit_once('|') {
+        (Game::from_fen(position.split('|').next()?).ok()?, result.trim())
+    } else if let Ok(epd) = Game::from_epd(line) {
+        let result = epd.operation("c9")?.first()?;
+        return Some((*epd.game.board(), parse_result(result)?));
+    } else if let Some((position, rest)) = line.split_once('[') {
+        (Game::from_fen(position).ok()?, rest.split(']').next()?)
+    } else {
+        return None;
+    };
+
+    Some((*game.board(), parse_result(result)?))
+}
+
+fn parse_result(result: &str) -> Option<f64> {
+    match result {
+        "1-0" | "1.0" | "1" => Some(1.0),
+        "1/2-1/2" | "0.5" => Some(0.5),
+        "0-1" | "0.0" | "0" => Some(0.0),
+        _ => None,
+    }
+}
+
+/// Tapered evaluation from white's view
+fn evaluate(trace: &PestoTrace, params: &[f64]) -> f64 {
+    let dot = |coeffs: &[(u16, i16)]| coeffs.iter().map(|(i, c)| params[*i as usize] * *c as f64).sum::<f64>();
+    let phase = trace.phase as f64;
+
+    (dot(&trace.mid) * phase + dot(&trace.end) * (24.0 - phase)) / 24.0
+}
+
+fn sigmoid(k: f64, eval: f64) -> f64 {
+    1.0 / (1.0 + 10_f64.powf(-k * eval / 400.0))
+}
+
+/// Run `f` over the entries split across all threads and sum up the results
+fn par_sum<T: Send, F: Fn(&[Entry]) -> T + Sync>(entries: &[Entry], f: F, add: impl Fn(T, T) -> T) -> T {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = entries.len().div_ceil(threads).max(1);
+
+    std::thread::scope(|s| {
+        let handles = entries.chunks(chunk).map(|c| s.spawn(|| f(c))).collect::<Vec<_>>();
+        handles.into_iter().map(|h| h.join().unwrap()).reduce(add)
+    }).unwrap_or_else(|| f(&[]))
+}
+
+fn error(entries: &[Entry], params: &[f64], k: f64) -> f64 {
+    let sum = par_sum(entries, |chunk| chunk.iter().map(|e| {
+        (e.result - sigmoid(k, evaluate(&e.trace, params))).powi(2)
+    }).sum::<f64>(), |a, b| a + b);
+
+    sum / entries.len().max(1) as f64
+}
+
+fn gradient(entries: &[Entry], params: &[f64], k: f64) -> Box<[f64; PESTO_PARAMS]> {
+    let mut grad = par_sum(entries, |chunk| {
+        let mut grad = Box::new([0.0; PESTO_PARAMS]);
+
+        for e in chunk {
+            let s = sigmoid(k, evaluate(&e.trace, params));
+            let d = (s - e.result) * s * (1.0 - s);
+            let phase = e.trace.phase as f64 / 24.0;
+
+            for (i, c) in e.trace.mid.iter() {
+                grad[*i as usize] += d * *c as f64 * phase;
+            }
+
+            for (i, c) in e.trace.end.iter() {
+                grad[*i as usize] += d * *c as f64 * (1.0 - phase);
+            }
+        }
+
+        grad
+    }, |mut a, b| {
+        a.iter_mut().zip(b.iter()).for_each(|(a, b)| *a += b);
+        a
+    });
+
+    // constant factors of the derivative, which Adam mostly cancels out anyway
+    let scale = 2.0 * k * core::f64::consts::LN_10 / 400.0 / entries.len().max(1) as f64;
+    grad.iter_mut().for_each(|g| *g *= scale);
+    grad
+}
+
+/// Find the `k` that minimises the error with the current parameters
+fn fit_k(entries: &[Entry], params: &[f64]) -> f64 {
+    let mut best = 1.0;
+    let mut step = 1.0;
+
+    for _ in 0..4 {
+        let candidates = (-10..=10).map(|i| best + i as f64 * step / 10.0).filter(|k| *k > 0.0);
+        best = candidates
+            .map(|k| (k, error(entries, params, k)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(best, |(k, _)| k);
+        step /= 10.0;
+    }
+
+    best
+}
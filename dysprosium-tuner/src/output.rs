@@ -0,0 +1,43 @@
+use std::io::{self, Write};
+
+const PIECES: [&str; 6] = ["Pawn", "Knight", "Bishop", "Rook", "Queen", "King"];
+
+/// Write the parameters as Rust source in the layout of `src/eval_params.rs`
+pub fn write_params<W: Write>(w: &mut W, params: &[f64]) -> io::Result<()> {
+    let p = |i: usize| params[i].round() as i16;
+    let values = |start: usize| (start..start + 6).map(|i| p(i).to_string()).collect::<Vec<_>>().join(", ");
+
+    writeln!(w, "// Evaluation parameters, regenerated by `dysprosium-tuner`")?;
+    writeln!(w)?;
+    writeln!(w, "const PIECE_VALUE_MID: [i16; 6] = [{}];", values(0))?;
+    writeln!(w, "#[cfg(feature = \"eval-pesto\")]")?;
+    writeln!(w, "const PIECE_VALUE_END: [i16; 6] = [{}];", values(6))?;
+    writeln!(w, "#[cfg(feature = \"eval-pesto\")]")?;
+    writeln!(w, "const ROOK_OPEN_FILE: i16 = {};", p(780))?;
+    writeln!(w, "#[cfg(feature = \"eval-pesto\")]")?;
+    writeln!(w, "const PAWN_SHIELD: i16 = {};", p(781))?;
+    writeln!(w)?;
+    writeln!(w, "// a1 ----> h1")?;
+    writeln!(w, "// |")?;
+    writeln!(w, "// v")?;
+    writeln!(w, "// a8")?;
+    write_table(w, "PIECE_SQUARE_TABLE_MID", &params[12..396])?;
+    writeln!(w)?;
+    write_table(w, "PIECE_SQUARE_TABLE_END", &params[396..780])
+}
+
+fn write_table<W: Write>(w: &mut W, name: &str, table: &[f64]) -> io::Result<()> {
+    writeln!(w, "#[cfg(feature = \"eval-pesto\")]")?;
+    writeln!(w, "const {name}: [i16; 64 * 6] = [")?;
+
+    for (piece, squares) in PIECES.iter().zip(table.chunks(64)) {
+        writeln!(w, "    // {piece}")?;
+
+        for rank in squares.chunks(8) {
+            let rank = rank.iter().map(|v| format!(" {:4},", v.round() as i16)).collect::<String>();
+            writeln!(w, "   {rank}")?;
+        }
+    }
+
+    writeln!(w, "];")
+}
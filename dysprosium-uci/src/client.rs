@@ -1,29 +1,47 @@
-use std::{str::FromStr, time::{Duration, Instant}};
+use std::{str::FromStr, sync::Arc, thread::JoinHandle, time::{Duration, Instant}};
 
 use crate::*;
 
-use dysprosium::Engine;
+use dysprosium::{book::{Book, BookSelection}, Engine, SearchLimits};
 
 fn print_uci_info() {
     println!("id name dysprosium v{VERSION}");
     println!("id author funnsam");
     println!("option name Hash type spin default {DEFAULT_HASH_SIZE_MB} min 1 max 16384");
     println!("option name Threads type spin default {DEFAULT_THREADS} min 1 max 256");
+    println!("option name Ponder type check default false");
+    println!("option name MultiPV type spin default 1 min 1 max 256");
+    println!("option name OwnBook type check default false");
+    println!("option name BookFile type string default <empty>");
+    println!("option name SyzygyPath type string default <empty>");
+    #[cfg(feature = "eval-nnue")]
+    println!("option name EvalFile type string default <empty>");
+
+    #[cfg(feature = "tune")]
+    for t in dysprosium::tunables::TUNABLES {
+        println!("option name {} type spin default {} min {} max {}", t.name, t.default, t.min, t.max);
+    }
 }
 
 pub struct State {
-    engine: Engine,
+    engine: Arc<Engine>,
+    search: Option<JoinHandle<()>>,
     debug_mode: bool,
+    own_book: bool,
+    book: Option<Book>,
 }
 
 impl State {
     pub fn new() -> Self {
-        let mut engine = Engine::new(Game::new(chess::Board::default()), DEFAULT_HASH_SIZE_MB * MB);
-        engine.start_smp(DEFAULT_THREADS - 1);
+        let mut engine = Arc::new(Engine::new(Game::new(chess::Board::default()), DEFAULT_HASH_SIZE_MB * MB));
+        Arc::get_mut(&mut engine).unwrap().start_smp(DEFAULT_THREADS - 1);
 
         Self {
             engine,
+            search: None,
             debug_mode: false,
+            own_book: false,
+            book: None,
         }
     }
 
@@ -34,77 +52,203 @@ impl State {
                 println!("uciok");
             },
             Some(uci::UciCommand::SetOption(name, value)) => match name.to_ascii_lowercase().as_str() {
-                "hash" => self.engine.resize_hash(value.unwrap().parse::<usize>().unwrap() * MB),
+                "hash" => self.engine_mut().resize_hash(value.unwrap().parse::<usize>().unwrap() * MB),
                 "threads" => {
-                    self.engine.kill_smp();
-                    self.engine.start_smp(value.unwrap().parse::<usize>().unwrap() - 1);
+                    let engine = self.engine_mut();
+                    engine.kill_smp();
+                    engine.start_smp(value.unwrap().parse::<usize>().unwrap() - 1);
+                },
+                "ponder" => {},
+                "multipv" => self.engine_mut().set_multi_pv(value.unwrap().parse().unwrap()),
+                "ownbook" => self.own_book = value == Some("true"),
+                "bookfile" => self.book = match value.filter(|v| *v != "<empty>") {
+                    Some(path) => Book::open(path).inspect_err(|e| println!("info string failed to open book: {e}")).ok(),
+                    None => None,
+                },
+                #[cfg(feature = "eval-nnue")]
+                "evalfile" => {
+                    let path = value.filter(|v| *v != "<empty>").unwrap_or("");
+                    if let Err(e) = self.engine_mut().set_eval_file(path) {
+                        println!("info string failed to load network: {e}");
+                    }
+                },
+                "syzygypath" => {
+                    let path = value.filter(|v| *v != "<empty>").unwrap_or("");
+                    if let Err(e) = self.engine_mut().set_syzygy_path(path) {
+                        println!("info string failed to load tablebases: {e}");
+                    }
+                },
+                #[cfg(feature = "tune")]
+                name if dysprosium::tunables::TUNABLES.iter().any(|t| t.name == name) => {
+                    // the search reads tunables as it goes
+                    self.stop_search();
+
+                    if !value.and_then(|v| v.parse().ok()).is_some_and(|v| dysprosium::tunables::set_tunable(name, v)) {
+                        println!("info string got invalid setoption");
+                    }
                 },
                 _ => println!("info string got invalid setoption"),
             },
             Some(uci::UciCommand::Debug(d)) => self.debug_mode = d,
             Some(uci::UciCommand::IsReady) => println!("readyok"),
-            Some(uci::UciCommand::Quit) => std::process::exit(0),
-            Some(uci::UciCommand::UciNewGame) => {},
+            Some(uci::UciCommand::Quit) => {
+                self.stop_search();
+                std::process::exit(0);
+            },
+            Some(uci::UciCommand::Stop) => self.stop_search(),
+            Some(uci::UciCommand::PonderHit) => if let Some(search) = &self.search {
+                self.engine.ponderhit();
+                search.thread().unpark();
+            },
+            Some(uci::UciCommand::UciNewGame) => self.engine_mut().new_game(),
             Some(uci::UciCommand::Position { mut position, moves }) => {
+                self.stop_search();
+
                 for m in moves {
-                    position = position.make_move(m);
+                    match m {
+                        Ok(m) if position.board().legal(m) => position = position.make_move(m),
+                        Ok(m) => {
+                            println!("info string illegal move {m}");
+                            break;
+                        },
+                        Err(m) => {
+                            println!("info string invalid move {m}");
+                            break;
+                        },
+                    }
                 }
 
                 *self.engine.game.write() = position;
             },
             Some(uci::UciCommand::Move(m)) => {
+                self.stop_search();
                 *self.engine.game.write() = self.engine.game.read().make_move(m)
             },
-            Some(uci::UciCommand::Go { depth: target_depth, movetime, wtime, btime, movestogo }) => {
-                let tc = if matches!(self.engine.game.read().board().side_to_move(), chess::Color::White) {
+            Some(uci::UciCommand::Go { depth, nodes, mate, searchmoves, movetime, wtime, btime, movestogo, infinite, ponder }) => {
+                if let Some(m) = self.book_move().filter(|_| !infinite && !ponder) {
+                    println!("bestmove {m}");
+                    return;
+                }
+
+                let engine = self.engine_mut();
+
+                for m in searchmoves.iter().filter(|m| !engine.game.read().board().legal(**m)) {
+                    println!("info string illegal move {m} in searchmoves");
+                }
+
+                let tc = if matches!(engine.game.read().board().side_to_move(), chess::Color::White) {
                     wtime
                 } else {
                     btime
                 };
-                if let Some(mt) = movetime {
-                    self.engine.allow_for(mt);
+                if infinite {
+                    engine.allow_for(Duration::MAX);
+                } else if let Some(mt) = movetime {
+                    engine.allow_for(mt);
                 } else if let Some(tc) = tc {
-                    self.engine.time_control(movestogo, tc);
+                    engine.time_control(movestogo, tc);
                 } else {
-                    self.engine.allow_for(Duration::MAX);
+                    engine.allow_for(Duration::MAX);
                 }
 
-                let mov = self.best_move(target_depth);
-                if self.debug_mode {
-                    let full = 1000 * self.engine.tt_used() / self.engine.tt_size();
-
-                    println!("info hashfull {full}");
-                    self.engine.dump_debug();
-                }
-                println!("bestmove {mov}");
+                engine.set_limits(if infinite {
+                    SearchLimits { search_moves: searchmoves, ..Default::default() }
+                } else {
+                    SearchLimits { depth, nodes, mate, search_moves: searchmoves }
+                });
+                self.start_search(infinite, ponder);
             },
             Some(uci::UciCommand::D) => print!("{:#}", self.engine.game.read()),
             Some(uci::UciCommand::Eval) => println!(
                 "{:#}Eval: {}",
                 self.engine.game.read(),
-                evaluate_static(self.engine.game.read().board()),
+                evaluate_static(&self.engine.game.read()),
             ),
+            Some(uci::UciCommand::Perft(depth)) => {
+                self.stop_search();
+
+                let start = Instant::now();
+                let nodes = self.engine.game.read().perft(depth);
+                let time = start.elapsed();
+
+                println!("Nodes searched: {nodes}");
+                println!("Time: {}ms ({:.0} nps)", time.as_millis(), nodes as f64 / time.as_secs_f64());
+            },
+            Some(uci::UciCommand::Divide(depth)) => {
+                self.stop_search();
+
+                let divide = self.engine.game.read().divide(depth);
+                for (m, nodes) in divide.iter() {
+                    println!("{m}: {nodes}");
+                }
+
+                println!();
+                println!("Nodes searched: {}", divide.iter().map(|(_, n)| n).sum::<usize>());
+            },
             Some(uci::UciCommand::Bench) => self.bench(),
+            Some(uci::UciCommand::Spsa) => for t in dysprosium::tunables::TUNABLES {
+                println!("{t}");
+            },
             None => {},
         }
     }
 
-    fn best_move(&mut self, target_depth: Option<usize>) -> chess::ChessMove {
-        self.engine.best_move(|engine, (best, eval, depth)| {
-            let time = engine.elapsed();
-            let nodes = engine.nodes();
+    /// Wait for the running search to finish, if there is one.
+    pub fn wait_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.join().unwrap();
+        }
+    }
 
-            println!(
-                "info score {eval:#} depth {depth} nodes {nodes} time {} nps {} pv {}",
-                time.as_millis(),
-                (nodes as f64 / time.as_secs_f64()) as u64,
-                engine.find_pv(best, if self.debug_mode { 100 } else { 20 }).into_iter()
-                .map(|m| m.to_string())
-                .collect::<Vec<_>>()
-                .join(" "),
-            );
-            target_depth.map_or(true, |td| td > depth)
-        }).0
+    /// Stop the running search, if there is one. Commands that change the engine's state do
+    /// this first, since an infinite or ponder search would otherwise never end.
+    fn stop_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.engine.stop();
+            search.thread().unpark();
+            search.join().unwrap();
+        }
+    }
+
+    /// Exclusive access to the engine. Any running search is stopped first, since it holds the
+    /// other reference to it.
+    fn engine_mut(&mut self) -> &mut Engine {
+        self.stop_search();
+        Arc::get_mut(&mut self.engine).unwrap()
+    }
+
+    fn book_move(&mut self) -> Option<chess::ChessMove> {
+        self.stop_search();
+        let book = self.book.as_ref().filter(|_| self.own_book)?;
+        book.select(&self.engine.game.read(), BookSelection::Weighted)
+    }
+
+    fn start_search(&mut self, infinite: bool, ponder: bool) {
+        let engine = Arc::clone(&self.engine);
+        let debug_mode = self.debug_mode;
+
+        engine.clear_stop();
+        engine.set_pondering(ponder);
+        self.search = Some(std::thread::spawn(move || {
+            let mov = best_move(&engine, debug_mode);
+
+            // `bestmove` can't be sent before `stop` during an infinite search, or before `stop`
+            // or `ponderhit` while pondering
+            while (infinite || engine.pondering()) && !engine.stopped() {
+                std::thread::park();
+            }
+
+            if debug_mode {
+                let full = 1000 * engine.tt_used() / engine.tt_size();
+
+                println!("info hashfull {full}");
+                engine.dump_debug();
+            }
+            match engine.find_pv(mov, 2).get(1) {
+                Some(ponder) => println!("bestmove {mov} ponder {ponder}"),
+                None => println!("bestmove {mov}"),
+            }
+        }));
     }
 
     fn bench(&mut self) {
@@ -163,16 +307,49 @@ impl State {
         let start = Instant::now();
         let mut nodes = 0;
 
-        self.engine.allow_for(Duration::MAX);
+        let engine = self.engine_mut();
+        engine.allow_for(Duration::MAX);
+        engine.set_limits(SearchLimits { depth: Some(8), ..Default::default() });
 
         for fen in TEST_FENS {
-            *self.engine.game.write() = Game::from_str(fen).unwrap();
-            self.engine.best_move(|_, (_, _, depth)| depth < 8);
+            *engine.game.write() = Game::from_str(fen).unwrap();
+            engine.best_move(|_, _| true);
 
-            nodes += self.engine.nodes();
+            nodes += engine.nodes();
         }
 
         let nps = (nodes as f64 / start.elapsed().as_secs_f64()).round();
         println!("{nodes} nodes {nps} nps");
     }
 }
+
+fn best_move(engine: &Engine, debug_mode: bool) -> chess::ChessMove {
+    engine.best_move(|engine, (_, _, depth)| {
+        let time = engine.elapsed();
+        let nodes = engine.nodes();
+
+        for (i, (best, eval)) in engine.lines().into_iter().enumerate() {
+            println!(
+                "info multipv {} score {eval:#} depth {depth} nodes {nodes} time {} nps {} tbhits {} pv {}",
+                i + 1,
+                time.as_millis(),
+                (nodes as f64 / time.as_secs_f64()) as u64,
+                engine.tb_hits(),
+                engine.find_pv(best, if debug_mode { 100 } else { 20 }).into_iter()
+                .map(|m| m.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            );
+        }
+        true
+    }).0
+}
+
+#[test]
+fn test_position_stops_search() {
+    let mut state = State::new();
+    state.handle_command(uci::parse_command("go infinite".split_whitespace()));
+    state.handle_command(uci::parse_command("position startpos".split_whitespace()));
+
+    assert!(state.search.is_none());
+}
@@ -26,6 +26,7 @@ fn main() {
             a = args.next();
         }
 
+        client.wait_search();
         std::process::exit(0);
     }
 
@@ -36,4 +37,6 @@ fn main() {
             client.handle_command(uci::parse_command(tokens));
         }
     }
+
+    client.wait_search();
 }
@@ -10,39 +10,32 @@ pub enum UciCommand<'a> {
     UciNewGame,
     Position {
         position: dysprosium::Game,
-        moves: Vec<ChessMove>,
+        /// Ends with the first token that isn't a move, if there is one
+        moves: Vec<Result<ChessMove, &'a str>>,
     },
     Go {
         depth: Option<usize>,
+        nodes: Option<usize>,
+        mate: Option<usize>,
+        searchmoves: Vec<ChessMove>,
         movetime: Option<Duration>,
         wtime: Option<TimeControl>,
         btime: Option<TimeControl>,
         movestogo: Option<usize>,
+        infinite: bool,
+        ponder: bool,
     },
+    Perft(usize),
+    Divide(usize),
+    Stop,
+    PonderHit,
     SetOption(&'a str, Option<&'a str>),
     Quit,
     D,
     Eval,
     Move(ChessMove),
     Bench,
-}
-
-fn move_from_uci(m: &str) -> ChessMove {
-    let src = &m[0..2];
-    let src = Square::new(((src.as_bytes()[1] - b'1') << 3) + (src.as_bytes()[0] - b'a'));
-
-    let dst = &m[2..4];
-    let dst = Square::new(((dst.as_bytes()[1] - b'1') << 3) + (dst.as_bytes()[0] - b'a'));
-
-    let piece = m.as_bytes().get(4).and_then(|p| match p {
-        b'n' => Some(Piece::Knight),
-        b'b' => Some(Piece::Bishop),
-        b'q' => Some(Piece::Queen),
-        b'r' => Some(Piece::Rook),
-        _ => None,
-    });
-
-    ChessMove::new(src, dst, piece)
+    Spsa,
 }
 
 pub fn parse_command<'a>(mut token: core::str::SplitWhitespace<'a>) -> Option<UciCommand<'a>> {
@@ -74,8 +67,12 @@ pub fn parse_command<'a>(mut token: core::str::SplitWhitespace<'a>) -> Option<Uc
                 return None;
             };
 
-            while let Some(m) = token.next() {
-                moves.push(move_from_uci(m));
+            for m in token {
+                let m = dysprosium::parse_uci_move(m).map_err(|_| m);
+                let invalid = m.is_err();
+                moves.push(m);
+
+                if invalid { break };
             }
 
             Some(UciCommand::Position {
@@ -85,28 +82,46 @@ pub fn parse_command<'a>(mut token: core::str::SplitWhitespace<'a>) -> Option<Uc
         },
         Some("go") => {
             let mut depth = None;
+            let mut nodes = None;
+            let mut mate = None;
+            let mut searchmoves = Vec::new();
             let mut movetime = None;
             let mut wtime = None;
             let mut btime = None;
             let mut winc = None;
             let mut binc = None;
             let mut movestogo = None;
+            let mut infinite = false;
+            let mut ponder = false;
+            let mut token = token.peekable();
 
             while let Some(t) = token.next() {
                 match t {
                     "depth" => depth = token.next().and_then(|t| t.parse().ok()),
+                    "perft" => return Some(UciCommand::Perft(token.next()?.parse().ok()?)),
+                    "nodes" => nodes = token.next().and_then(|t| t.parse().ok()),
+                    "mate" => mate = token.next().and_then(|t| t.parse().ok()),
+                    "searchmoves" => while let Some(m) = token.peek().and_then(|t| dysprosium::parse_uci_move(t).ok()) {
+                        token.next();
+                        searchmoves.push(m);
+                    },
                     "movetime" => movetime = token.next().and_then(|t| Some(Duration::from_millis(t.parse().ok()?))),
                     "wtime" => wtime = token.next().and_then(|t| t.parse().ok()),
                     "btime" => btime = token.next().and_then(|t| t.parse().ok()),
                     "winc" => winc = token.next().and_then(|t| t.parse().ok()),
                     "binc" => binc = token.next().and_then(|t| t.parse().ok()),
                     "movestogo" => movestogo = token.next().and_then(|t| t.parse().ok()),
+                    "infinite" => infinite = true,
+                    "ponder" => ponder = true,
                     _ => {},
                 }
             }
 
             Some(UciCommand::Go {
                 depth,
+                nodes,
+                mate,
+                searchmoves,
                 movetime,
                 wtime: wtime.map(|time| TimeControl {
                     time_left: time,
@@ -117,8 +132,13 @@ pub fn parse_command<'a>(mut token: core::str::SplitWhitespace<'a>) -> Option<Uc
                     time_incr: binc.unwrap_or(0),
                 }),
                 movestogo,
+                infinite,
+                ponder,
             })
         },
+        Some("divide") => Some(UciCommand::Divide(token.next()?.parse().ok()?)),
+        Some("stop") => Some(UciCommand::Stop),
+        Some("ponderhit") => Some(UciCommand::PonderHit),
         Some("setoption") => {
             token.next();
             let name = token.next()?;
@@ -129,8 +149,9 @@ pub fn parse_command<'a>(mut token: core::str::SplitWhitespace<'a>) -> Option<Uc
         Some("quit") => Some(UciCommand::Quit),
         Some("d") => Some(UciCommand::D),
         Some("eval") => Some(UciCommand::Eval),
-        Some("move") => Some(UciCommand::Move(move_from_uci(token.next()?))),
+        Some("move") => Some(UciCommand::Move(dysprosium::parse_uci_move(token.next()?).ok()?)),
         Some("bench") => Some(UciCommand::Bench),
+        Some("spsa") => Some(UciCommand::Spsa),
         Some(_) => parse_command(token),
         None => None,
     }
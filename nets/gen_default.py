@@ -0,0 +1,58 @@
+#!/usr/bin/env python3
+# Builds nets/default.nnue, the stand-in network embedded when no trained one is loaded. It is
+# the untapered average of the PeSTO middle and end game tables from src/eval_params.rs: 96 of
+# the hidden neurons carry the piece square values of one side each, and the output layer adds
+# them up for the side to move and subtracts them for the other one.
+
+import re
+import struct
+from pathlib import Path
+
+ROOT = Path(__file__).resolve().parent.parent
+
+# must match src/nnue.rs
+HIDDEN = 128
+QA = 255
+QB = 64
+SCALE = 400
+
+# feature weight per centipawn, small enough to stay inside the clipped ReLU range of `QA`
+K = 0.22
+# bias so that negative piece square values still make it through the ReLU
+BIAS = 20
+
+params = (ROOT / "src/eval_params.rs").read_text()
+
+def table(name):
+    body = re.search(r"const " + name + r": \[i16; [^\]]+\] = \[(.*?)\];", params, re.S).group(1)
+    body = re.sub(r"//[^\n]*", "", body)
+    return [int(x.replace("_", "")) for x in re.findall(r"-?[\d_]+", body)]
+
+psqt_mid = table("PIECE_SQUARE_TABLE_MID")
+psqt_end = table("PIECE_SQUARE_TABLE_END")
+value_mid = table("PIECE_VALUE_MID")
+value_end = table("PIECE_VALUE_END")
+assert len(psqt_mid) == len(psqt_end) == 384 and len(value_mid) == len(value_end) == 6
+
+def value(piece, sq):
+    return (psqt_mid[piece * 64 + sq] + value_mid[piece] + psqt_end[piece * 64 + sq] + value_end[piece]) / 2
+
+# features are `384 * side + 64 * piece + square` from the accumulator's own point of view, and
+# each side gets 48 neurons, one per piece and file
+feature_weights = [[0] * HIDDEN for _ in range(768)]
+for side in range(2):
+    for piece in range(6):
+        for sq in range(64):
+            neuron = side * 48 + piece * 8 + (sq & 7)
+            feature_weights[384 * side + 64 * piece + sq][neuron] = round(K * value(piece, sq if side == 0 else sq ^ 56))
+
+feature_bias = [BIAS if n < 96 else 0 for n in range(HIDDEN)]
+
+out = round(QA * QB / (SCALE * K))
+output_weights = [out if n < 48 else -out if n < 96 else 0 for n in range(HIDDEN)] + [0] * HIDDEN
+
+def pack(values):
+    return struct.pack("<%dh" % len(values), *values)
+
+data = b"".join(pack(row) for row in feature_weights) + pack(feature_bias) + pack(output_weights) + pack([0])
+(ROOT / "nets/default.nnue").write_bytes(data)
@@ -1,6 +1,10 @@
 use chess::*;
 
 /// Evaluation score in centipawns. +ve is side to move better and -ve is worse
+///
+/// Mate scores inside the search count plies from the root, so a mate found
+/// deeper in the tree is worth less. The transposition table stores them
+/// relative to the node instead, see [`Eval::to_tt`] and [`Eval::from_tt`].
 /// ```text
 ///    ┌┬┬─ mate in n              ┌┬┬─ mate in !n
 /// 10_000…b                    01_111…b
@@ -17,14 +21,19 @@ impl Eval {
     pub const MAX: Self = Self(i16::MAX);
     pub const MIN: Self = Self(-Self::MAX.0);
     pub const M0: Self = Self(Self::MAX.0);
+    /// Score of a tablebase win, above any static evaluation but below all mates
+    pub const TB_WIN: Self = Self(15_000);
 
+    /// Side to move mates in `ply` plies
     #[inline]
-    pub fn incr_mate(self) -> Self {
-        match self.0 as u16 >> 14 {
-            1 => Self(self.0 - 1),
-            2 => Self(self.0 + 1),
-            _ => self,
-        }
+    pub const fn mate_in(ply: usize) -> Self {
+        Self(Self::M0.0 - ply as i16)
+    }
+
+    /// Side to move gets mated in `ply` plies
+    #[inline]
+    pub const fn mated_in(ply: usize) -> Self {
+        Self(!Self::M0.0 + ply as i16)
     }
 
     #[inline]
@@ -36,6 +45,37 @@ impl Eval {
     pub fn is_positive_mate(self) -> bool {
         self.0 as u16 >> 14 == 1
     }
+
+    /// Number of plies until mate, if this is a mate score
+    #[inline]
+    pub fn mate_plies(self) -> Option<i16> {
+        match self.0 as u16 >> 14 {
+            1 => Some(!self.0 & 0x3fff),
+            2 => Some(self.0 & 0x3fff),
+            _ => None,
+        }
+    }
+
+    /// Convert a root relative mate score found at `ply` into a node relative one for storing
+    /// into the transposition table
+    #[inline]
+    pub fn to_tt(self, ply: usize) -> Self {
+        match self.0 as u16 >> 14 {
+            1 => Self(self.0.saturating_add(ply as i16)),
+            2 => Self(self.0.saturating_sub(ply as i16)),
+            _ => self,
+        }
+    }
+
+    /// Inverse of [`Eval::to_tt`]
+    #[inline]
+    pub fn from_tt(eval: Self, ply: usize) -> Self {
+        match eval.0 as u16 >> 14 {
+            1 => Self(eval.0 - ply as i16),
+            2 => Self(eval.0 + ply as i16),
+            _ => eval,
+        }
+    }
 }
 
 impl core::ops::Add<i16> for Eval {
@@ -80,17 +120,19 @@ impl core::ops::Neg for Eval {
 
 impl core::fmt::Display for Eval {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let moves = self.mate_plies().map(|plies| (plies + 1) / 2);
+
         if f.alternate() {
-            match self.0 as u16 >> 14 {
-                1 => write!(f, "mate {}", !self.0 & 0x3fff),
-                2 => write!(f, "mate -{}", self.0 & 0x3fff),
-                _ => write!(f, "cp {}", self.0),
+            match (moves, self.is_positive_mate()) {
+                (Some(m), true) => write!(f, "mate {m}"),
+                (Some(m), false) => write!(f, "mate -{m}"),
+                (None, _) => write!(f, "cp {}", self.0),
             }
         } else {
-            match self.0 as u16 >> 14 {
-                1 => write!(f, "#{}", ((!self.0 & 0x3fff) + 1) / 2),
-                2 => write!(f, "#-{}", ((self.0 & 0x3fff) + 1) / 2),
-                _ => write!(f, "{}cp", self.0),
+            match (moves, self.is_positive_mate()) {
+                (Some(m), true) => write!(f, "#{m}"),
+                (Some(m), false) => write!(f, "#-{m}"),
+                (None, _) => write!(f, "{}cp", self.0),
             }
         }
     }
@@ -100,9 +142,9 @@ impl core::fmt::Display for Eval {
 #[cfg(test)]
 fn test_eval() {
     let m0 = Eval::M0;
-    let m1 = m0.incr_mate();
+    let m1 = Eval::mate_in(1);
     let m_0 = -m0;
-    let m_1 = m_0.incr_mate();
+    let m_1 = Eval::mated_in(1);
 
     assert_eq!(m0.0, 0x7fff);
     assert_eq!(m0.to_string(), "#0");
@@ -124,10 +166,60 @@ fn test_eval() {
     assert_eq!(-m1, m_1);
     assert_eq!(-m_0, m0);
     assert_eq!(-m_1, m1);
+
+    assert_eq!(Eval::mate_in(3), Eval(0x7ffc));
+    assert_eq!(Eval::mated_in(4), -Eval::mate_in(4));
+    assert_eq!(format!("{:#}", Eval::mate_in(3)), "mate 2");
+    assert_eq!(format!("{:#}", Eval::mated_in(4)), "mate -2");
+    assert_eq!(format!("{:#}", Eval(-35)), "cp -35");
+
+    // mate in 5 plies from the root found at ply 2 is a mate in 3 plies from that node
+    assert_eq!(Eval::mate_in(5).to_tt(2), Eval::mate_in(3));
+    assert_eq!(Eval::mated_in(5).to_tt(2), Eval::mated_in(3));
+    assert_eq!(Eval::from_tt(Eval::mate_in(3), 4), Eval::mate_in(7));
+    assert_eq!(Eval::from_tt(Eval::mated_in(3), 4), Eval::mated_in(7));
+    assert_eq!(Eval(120).to_tt(6), Eval(120));
+}
+
+#[test]
+#[cfg(all(test, feature = "eval-pesto"))]
+fn test_pesto_trace() {
+    use core::str::FromStr;
+
+    let params = pesto_params();
+    let dot = |coeffs: &[(u16, i16)]| coeffs.iter().map(|(i, c)| params[*i as usize] as i32 * *c as i32).sum::<i32>();
+
+    for fen in [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P3/P1NP1N2/1PP1QPPP/R4RK1 b - - 0 10",
+    ] {
+        let board = Board::from_str(fen).unwrap();
+        let trace = pesto_trace(&board);
+        let phase = trace.phase as i32;
+
+        let white = (dot(&trace.mid) * phase + dot(&trace.end) * (24 - phase)) / 24;
+        let stm = if board.side_to_move() == Color::White { white } else { -white };
+        assert_eq!(evaluate_pesto(&board), Eval(stm as i16), "{fen}");
+    }
+}
+
+/// Static evaluation of the position, by the network if the `eval-nnue` feature is enabled
+#[cfg(feature = "eval-nnue")]
+pub fn evaluate_static(game: &crate::Game) -> Eval {
+    game.accumulators().evaluate(game.board().side_to_move())
+}
+
+/// Static evaluation of the position, by PeSTO since the `eval-nnue` feature is disabled
+#[cfg(not(feature = "eval-nnue"))]
+pub fn evaluate_static(game: &crate::Game) -> Eval {
+    evaluate_pesto(game.board())
 }
 
 /// Mostly PeSTO's evaluation with rook on open file bonus
-pub fn evaluate_static(board: &Board) -> Eval {
+#[cfg(feature = "eval-pesto")]
+pub fn evaluate_pesto(board: &Board) -> Eval {
     let mut mid_game = [0, 0];
     let mut end_game = [0, 0];
     let mut phase = 0;
@@ -137,26 +229,8 @@ pub fn evaluate_static(board: &Board) -> Eval {
         let piece = unsafe { board.piece_on(square).unwrap_unchecked() };
         let color = unsafe { board.color_on(square).unwrap_unchecked() };
 
-        // rook on open file bonus
-        let rook_on_open_file = (piece == Piece::Rook
-            && (board.pieces(Piece::Pawn) & chess::get_file(square.get_file())).0 == 0
-        ) as i16 * 20;
-        let pawn_shield = if piece == Piece::King {
-            // TODO: open file penalty fails SPRT
-            //
-            // let mut open_files = 0;
-            // if let Some(sq) = square.left() {
-            //     open_files += ((board.pieces(Piece::Pawn) & board.color_combined(color) & chess::get_file(sq.get_file())).0 == 0) as i16;
-            // }
-            // if let Some(sq) = square.right() {
-            //     open_files += ((board.pieces(Piece::Pawn) & board.color_combined(color) & chess::get_file(sq.get_file())).0 == 0) as i16;
-            // }
-
-            let king_center = square.uforward(color);
-            let king_pawns = (board.pieces(Piece::Pawn) & (chess::get_king_moves(king_center) | BitBoard::from_square(king_center))).popcnt();
-
-            -(3_i16.saturating_sub(king_pawns as i16) * 15) // + open_files * 50)
-        } else { 0 };
+        let rook_on_open_file = rook_on_open_file(board, piece, square) as i16 * ROOK_OPEN_FILE;
+        let pawn_shield = -missing_shield_pawns(board, piece, square, color) * PAWN_SHIELD;
 
         let idx = (square.to_index() ^ (0b111_000 * (color == Color::Black) as usize)) | (piece.to_index() << 6);
         mid_game[color.to_index()] += rook_on_open_file + pawn_shield + PIECE_SQUARE_TABLE_MID[idx] + PIECE_VALUE_MID[piece.to_index()];
@@ -173,6 +247,101 @@ pub fn evaluate_static(board: &Board) -> Eval {
     Eval(((mg_eval as i32 * mg_phase as i32 + eg_eval as i32 * eg_phase as i32) / 24) as i16)
 }
 
+#[cfg(feature = "eval-pesto")]
+#[inline]
+fn rook_on_open_file(board: &Board, piece: Piece, square: Square) -> bool {
+    piece == Piece::Rook && (board.pieces(Piece::Pawn) & chess::get_file(square.get_file())).0 == 0
+}
+
+/// Number of pawns missing from the 3 needed to shield a king
+#[cfg(feature = "eval-pesto")]
+#[inline]
+fn missing_shield_pawns(board: &Board, piece: Piece, square: Square, color: Color) -> i16 {
+    if piece != Piece::King { return 0 };
+
+    // TODO: open file penalty fails SPRT
+    //
+    // let mut open_files = 0;
+    // if let Some(sq) = square.left() {
+    //     open_files += ((board.pieces(Piece::Pawn) & board.color_combined(color) & chess::get_file(sq.get_file())).0 == 0) as i16;
+    // }
+    // if let Some(sq) = square.right() {
+    //     open_files += ((board.pieces(Piece::Pawn) & board.color_combined(color) & chess::get_file(sq.get_file())).0 == 0) as i16;
+    // }
+
+    let king_center = square.uforward(color);
+    let king_pawns = (board.pieces(Piece::Pawn) & (chess::get_king_moves(king_center) | BitBoard::from_square(king_center))).popcnt();
+
+    3_i16.saturating_sub(king_pawns as i16)
+}
+
+/// Number of parameters of the PeSTO evaluation, see [`pesto_params`]
+#[cfg(feature = "eval-pesto")]
+pub const PESTO_PARAMS: usize = 6 + 6 + 384 + 384 + 2;
+
+/// All parameters of the PeSTO evaluation in the order of `eval_params.rs`: midgame and endgame
+/// piece values, midgame and endgame piece-square tables, then the rook on open file bonus and
+/// the pawn shield penalty
+#[cfg(feature = "eval-pesto")]
+pub fn pesto_params() -> [i16; PESTO_PARAMS] {
+    let mut params = [0; PESTO_PARAMS];
+
+    params[0..6].copy_from_slice(&PIECE_VALUE_MID);
+    params[6..12].copy_from_slice(&PIECE_VALUE_END);
+    params[12..396].copy_from_slice(&PIECE_SQUARE_TABLE_MID);
+    params[396..780].copy_from_slice(&PIECE_SQUARE_TABLE_END);
+    params[780] = ROOK_OPEN_FILE;
+    params[781] = PAWN_SHIELD;
+    params
+}
+
+/// Coefficients of each parameter in the midgame and endgame evaluations of a position, from
+/// white's view
+#[cfg(feature = "eval-pesto")]
+#[derive(Debug, Clone, Default)]
+pub struct PestoTrace {
+    pub mid: Vec<(u16, i16)>,
+    pub end: Vec<(u16, i16)>,
+    /// Midgame phase from 0 to 24
+    pub phase: u8,
+}
+
+/// Break down [`evaluate_pesto`] into its parameters, for tuning them
+#[cfg(feature = "eval-pesto")]
+pub fn pesto_trace(board: &Board) -> PestoTrace {
+    let mut mid = [0; PESTO_PARAMS];
+    let mut end = [0; PESTO_PARAMS];
+    let mut phase = 0;
+
+    for square in board.combined().into_iter() {
+        // SAFETY: only squares with things on it are checked
+        let piece = unsafe { board.piece_on(square).unwrap_unchecked() };
+        let color = unsafe { board.color_on(square).unwrap_unchecked() };
+        let sign = if color == Color::White { 1 } else { -1 };
+
+        let idx = (square.to_index() ^ (0b111_000 * (color == Color::Black) as usize)) | (piece.to_index() << 6);
+        mid[piece.to_index()] += sign;
+        end[6 + piece.to_index()] += sign;
+        mid[12 + idx] += sign;
+        end[396 + idx] += sign;
+
+        let open = rook_on_open_file(board, piece, square) as i16;
+        mid[780] += sign * open;
+        end[780] += sign * open;
+        mid[781] -= sign * missing_shield_pawns(board, piece, square, color);
+
+        phase += PIECE_PHASE[piece.to_index()];
+    }
+
+    let sparse = |coeffs: [i16; PESTO_PARAMS]| coeffs.into_iter()
+        .enumerate()
+        .filter(|(_, c)| *c != 0)
+        .map(|(i, c)| (i as u16, c))
+        .collect();
+
+    PestoTrace { mid: sparse(mid), end: sparse(end), phase: phase.min(24) }
+}
+
 /// Finds the current phase of the game. 0 is endgame and 24 is midgame.
 pub fn game_phase(board: &Board) -> u8 {
     board.combined().into_iter().map(|sq| PIECE_PHASE[unsafe { board.piece_on(sq).unwrap_unchecked() }.to_index()]).sum::<u8>().min(24)
@@ -187,124 +356,6 @@ pub const PIECE_VALUE: [i16; 6] = [
     200_00,
 ];
 
-const PIECE_VALUE_MID: [i16; 6] = [82, 337, 365, 477, 1025,  0];
-const PIECE_VALUE_END: [i16; 6] = [94, 281, 297, 512,  936,  0];
 const PIECE_PHASE: [u8; 6] = [0, 1, 1, 2, 4, 0];
 
-// a1 ----> h1
-// |
-// v
-// a8
-const PIECE_SQUARE_TABLE_MID: [i16; 64 * 6] = [
-    // Pawn
-      0,   0,   0,   0,   0,   0,  0,   0,
-    -35,  -1, -20, -23, -15,  24, 38, -22,
-    -26,  -4,  -4, -10,   3,   3, 33, -12,
-    -27,  -2,  -5,  12,  17,   6, 10, -25,
-    -14,  13,   6,  21,  23,  12, 17, -23,
-     -6,   7,  26,  31,  65,  56, 25, -20,
-     98, 134,  61,  95,  68, 126, 34, -11,
-      0,   0,   0,   0,   0,   0,  0,   0,
-    // Knight
-    -105, -21, -58, -33, -17, -28, -19,  -23,
-     -29, -53, -12,  -3,  -1,  18, -14,  -19,
-     -23,  -9,  12,  10,  19,  17,  25,  -16,
-     -13,   4,  16,  13,  28,  19,  21,   -8,
-      -9,  17,  19,  53,  37,  69,  18,   22,
-     -47,  60,  37,  65,  84, 129,  73,   44,
-     -73, -41,  72,  36,  23,  62,   7,  -17,
-    -167, -89, -34, -49,  61, -97, -15, -107,
-    // Bishop
-    -33,  -3, -14, -21, -13, -12, -39, -21,
-      4,  15,  16,   0,   7,  21,  33,   1,
-      0,  15,  15,  15,  14,  27,  18,  10,
-     -6,  13,  13,  26,  34,  12,  10,   4,
-     -4,   5,  19,  50,  37,  37,   7,  -2,
-    -16,  37,  43,  40,  35,  50,  37,  -2,
-    -26,  16, -18, -13,  30,  59,  18, -47,
-    -29,   4, -82, -37, -25, -42,   7,  -8,
-    // Rook
-    -19, -13,   1,  17, 16,  7, -37, -26,
-    -44, -16, -20,  -9, -1, 11,  -6, -71,
-    -45, -25, -16, -17,  3,  0,  -5, -33,
-    -36, -26, -12,  -1,  9, -7,   6, -23,
-    -24, -11,   7,  26, 24, 35,  -8, -20,
-     -5,  19,  26,  36, 17, 45,  61,  16,
-     27,  32,  58,  62, 80, 67,  26,  44,
-     32,  42,  32,  51, 63,  9,  31,  43,
-    // Queen
-     -1, -18,  -9,  10, -15, -25, -31, -50,
-    -35,  -8,  11,   2,   8,  15,  -3,   1,
-    -14,   2, -11,  -2,  -5,   2,  14,   5,
-     -9, -26,  -9, -10,  -2,  -4,   3,  -3,
-    -27, -27, -16, -16,  -1,  17,  -2,   1,
-    -13, -17,   7,   8,  29,  56,  47,  57,
-    -24, -39,  -5,   1, -16,  57,  28,  54,
-    -28,   0,  29,  12,  59,  44,  43,  45,
-    // King
-    -15,  36,  12, -54,   8, -28,  24,  14,
-      1,   7,  -8, -64, -43, -16,   9,   8,
-    -14, -14, -22, -46, -44, -30, -15, -27,
-    -49,  -1, -27, -39, -46, -44, -33, -51,
-    -17, -20, -12, -27, -30, -25, -14, -36,
-     -9,  24,   2, -16, -20,   6,  22, -22,
-     29,  -1, -20,  -7,  -8,  -4, -38, -29,
-    -65,  23,  16, -15, -56, -34,   2,  13,
-];
-
-const PIECE_SQUARE_TABLE_END: [i16; 64 * 6] = [
-    // Pawn
-      0,   0,   0,   0,   0,   0,   0,   0,
-     13,   8,   8,  10,  13,   0,   2,  -7,
-      4,   7,  -6,   1,   0,  -5,  -1,  -8,
-     13,   9,  -3,  -7,  -7,  -8,   3,  -1,
-     32,  24,  13,   5,  -2,   4,  17,  17,
-     94, 100,  85,  67,  56,  53,  82,  84,
-    178, 173, 158, 134, 147, 132, 165, 187,
-      0,   0,   0,   0,   0,   0,   0,   0,
-    // Knight
-    -29, -51, -23, -15, -22, -18, -50, -64,
-    -42, -20, -10,  -5,  -2, -20, -23, -44,
-    -23,  -3,  -1,  15,  10,  -3, -20, -22,
-    -18,  -6,  16,  25,  16,  17,   4, -18,
-    -17,   3,  22,  22,  22,  11,   8, -18,
-    -24, -20,  10,   9,  -1,  -9, -19, -41,
-    -25,  -8, -25,  -2,  -9, -25, -24, -52,
-    -58, -38, -13, -28, -31, -27, -63, -99,
-    // Bishop
-    -23,  -9, -23,  -5, -9, -16,  -5, -17,
-    -14, -18,  -7,  -1,  4,  -9, -15, -27,
-    -12,  -3,   8,  10, 13,   3,  -7, -15,
-     -6,   3,  13,  19,  7,  10,  -3,  -9,
-     -3,   9,  12,   9, 14,  10,   3,   2,
-      2,  -8,   0,  -1, -2,   6,   0,   4,
-     -8,  -4,   7, -12, -3, -13,  -4, -14,
-    -14, -21, -11,  -8, -7,  -9, -17, -24,
-    // Rook
-    -9,  2,  3, -1, -5, -13,   4, -20,
-    -6, -6,  0,  2, -9,  -9, -11,  -3,
-    -4,  0, -5, -1, -7, -12,  -8, -16,
-     3,  5,  8,  4, -5,  -6,  -8, -11,
-     4,  3, 13,  1,  2,   1,  -1,   2,
-     7,  7,  7,  5,  4,  -3,  -5,  -3,
-    11, 13, 13, 11, -3,   3,   8,   3,
-    13, 10, 18, 15, 12,  12,   8,   5,
-    // Queen
-    -33, -28, -22, -43,  -5, -32, -20, -41,
-    -22, -23, -30, -16, -16, -23, -36, -32,
-    -16, -27,  15,   6,   9,  17,  10,   5,
-    -18,  28,  19,  47,  31,  34,  39,  23,
-      3,  22,  24,  45,  57,  40,  57,  36,
-    -20,   6,   9,  49,  47,  35,  19,   9,
-    -17,  20,  32,  41,  58,  25,  30,   0,
-     -9,  22,  22,  27,  27,  19,  10,  20,
-    // King
-    -53, -34, -21, -11, -28, -14, -24, -43,
-    -27, -11,   4,  13,  14,   4,  -5, -17,
-    -19,  -3,  11,  21,  23,  16,   7,  -9,
-    -18,  -4,  21,  24,  27,  23,   9, -11,
-     -8,  22,  24,  27,  26,  33,  26,   3,
-     10,  17,  23,  15,  20,  45,  44,  13,
-    -12,  17,  14,  17,  17,  38,  23,  11,
-    -74, -35, -18, -18, -11,  15,   4, -17,
-];
+include!("eval_params.rs");
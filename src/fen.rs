@@ -0,0 +1,271 @@
+//! FEN and EPD reading and writing for [`Game`].
+
+use core::{fmt, str::FromStr};
+
+use crate::Game;
+
+/// Field of a FEN record, to say which one is broken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FenField {
+    Pieces,
+    SideToMove,
+    Castling,
+    EnPassant,
+    HalfmoveClock,
+    FullmoveNumber,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FenError {
+    Missing(FenField),
+    Invalid(FenField),
+    /// Every field is well formed, but the position can't happen in a game
+    IllegalPosition,
+    /// An EPD operation that isn't `opcode operands...;`
+    InvalidOperation(String),
+}
+
+impl fmt::Display for FenField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pieces => "piece placement",
+            Self::SideToMove => "side to move",
+            Self::Castling => "castling rights",
+            Self::EnPassant => "en passant square",
+            Self::HalfmoveClock => "halfmove clock",
+            Self::FullmoveNumber => "fullmove number",
+        })
+    }
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(field) => write!(f, "missing {field}"),
+            Self::Invalid(field) => write!(f, "invalid {field}"),
+            Self::IllegalPosition => write!(f, "illegal position"),
+            Self::InvalidOperation(op) => write!(f, "invalid EPD operation `{op}`"),
+        }
+    }
+}
+
+impl core::error::Error for FenError {}
+
+impl Game {
+    /// Read a FEN record. The halfmove clock and fullmove number may be left out, in which case
+    /// they are 0 and 1.
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let mut fields = fen.split_whitespace();
+        let game = board_fields(&mut fields)?;
+
+        let halfmove = fields.next().map_or(Ok(0), |f| f.parse().map_err(|_| FenError::Invalid(FenField::HalfmoveClock)))?;
+        let fullmove = fields.next().map_or(Ok(1), |f| f.parse::<usize>().map_err(|_| FenError::Invalid(FenField::FullmoveNumber)))?;
+
+        Ok(game.with_clocks(halfmove, fullmove))
+    }
+
+    /// Read an EPD record: the first 4 fields of a FEN followed by operations. The clocks are
+    /// taken from the `hmvc` and `fmvn` operations if there are any.
+    pub fn from_epd(epd: &str) -> Result<Epd, FenError> {
+        let game = board_fields(&mut epd.split_whitespace())?;
+
+        let mut operations = epd.trim_start();
+        for _ in 0..4 {
+            operations = operations.split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim_start());
+        }
+        let operations = parse_operations(operations)?;
+
+        let clock = |opcode, field| operand(&operations, opcode).map(|o| o.parse().map_err(|_| FenError::Invalid(field)));
+        let halfmove = clock("hmvc", FenField::HalfmoveClock).unwrap_or(Ok(0))?;
+        let fullmove = clock("fmvn", FenField::FullmoveNumber).unwrap_or(Ok(1))?;
+
+        Ok(Epd { game: game.with_clocks(halfmove, fullmove), operations })
+    }
+
+    pub fn to_fen(&self) -> String {
+        format!("{} {} {}", self.epd_fields(), self.fifty_move_counter(), self.fullmove_number())
+    }
+
+    /// The first 4 fields of the FEN, which is what EPD records start with
+    pub fn epd_fields(&self) -> String {
+        self.board().to_string().split_whitespace().take(4).collect::<Vec<_>>().join(" ")
+    }
+
+    fn with_clocks(self, halfmove: usize, fullmove: usize) -> Self {
+        let black = self.board().side_to_move() == chess::Color::Black;
+        // some writers start counting from 0
+        let game_ply = fullmove.saturating_sub(1) * 2 + black as usize;
+
+        Self::from_parts(*self.board(), halfmove, game_ply)
+    }
+}
+
+impl FromStr for Game {
+    type Err = FenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_fen(s)
+    }
+}
+
+/// Validate the 4 fields describing the board one by one, so that errors can name the broken one
+fn board_fields<'a>(fields: &mut impl Iterator<Item = &'a str>) -> Result<Game, FenError> {
+    let mut field = |name, valid: fn(&str) -> bool| {
+        let f = fields.next().ok_or(FenError::Missing(name))?;
+        if valid(f) { Ok(f) } else { Err(FenError::Invalid(name)) }
+    };
+
+    let pieces = field(FenField::Pieces, valid_pieces)?;
+    let side = field(FenField::SideToMove, |f| f == "w" || f == "b")?;
+    let castling = field(FenField::Castling, |f| {
+        f == "-" || (!f.is_empty() && f.chars().all(|c| "KQkq".contains(c)) && "KQkq".chars().all(|c| f.matches(c).count() <= 1))
+    })?;
+    let en_passant = field(FenField::EnPassant, |f| {
+        f == "-" || matches!(f.as_bytes(), [b'a'..=b'h', b'3' | b'6'])
+    })?;
+
+    chess::Board::from_str(&format!("{pieces} {side} {castling} {en_passant} 0 1"))
+        .map(Game::new)
+        .map_err(|_| FenError::IllegalPosition)
+}
+
+fn valid_pieces(pieces: &str) -> bool {
+    let ranks = pieces.split('/').collect::<Vec<_>>();
+
+    ranks.len() == 8 && ranks.iter().all(|rank| {
+        let mut squares = 0;
+
+        for c in rank.chars() {
+            match c {
+                '1'..='8' => squares += c as usize - '0' as usize,
+                'p' | 'n' | 'b' | 'r' | 'q' | 'k' | 'P' | 'N' | 'B' | 'R' | 'Q' | 'K' => squares += 1,
+                _ => return false,
+            }
+        }
+
+        squares == 8
+    })
+}
+
+/// A position from an EPD record with its operations
+#[derive(Debug, Clone)]
+pub struct Epd {
+    pub game: Game,
+    /// Opcodes with their operands in order, with quoted operands unquoted
+    pub operations: Vec<(String, Vec<String>)>,
+}
+
+impl Epd {
+    pub fn operation(&self, opcode: &str) -> Option<&[String]> {
+        self.operations.iter().find(|(op, _)| op == opcode).map(|(_, operands)| operands.as_slice())
+    }
+
+    /// Best moves (`bm`) in SAN
+    pub fn best_moves(&self) -> &[String] { self.operation("bm").unwrap_or_default() }
+
+    /// Moves to avoid (`am`) in SAN
+    pub fn avoid_moves(&self) -> &[String] { self.operation("am").unwrap_or_default() }
+
+    pub fn id(&self) -> Option<&str> { operand(&self.operations, "id") }
+
+    /// The primary comment (`c0`)
+    pub fn comment(&self) -> Option<&str> { operand(&self.operations, "c0") }
+
+    /// Whether a move solves the position: it is one of the best moves if there are any, and not
+    /// one of the moves to avoid. Moves in the record that don't parse as SAN are left out.
+    pub fn accepts(&self, mov: chess::ChessMove) -> bool {
+        let matches = |moves: &[String]| moves.iter().any(|m| self.game.parse_san(m) == Ok(mov));
+
+        (self.best_moves().is_empty() || matches(self.best_moves())) && !matches(self.avoid_moves())
+    }
+}
+
+impl FromStr for Epd {
+    type Err = FenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Game::from_epd(s)
+    }
+}
+
+fn operand<'a>(operations: &'a [(String, Vec<String>)], opcode: &str) -> Option<&'a str> {
+    operations.iter().find(|(op, _)| op == opcode)?.1.first().map(String::as_str)
+}
+
+fn parse_operations(s: &str) -> Result<Vec<(String, Vec<String>)>, FenError> {
+    let mut operations = Vec::new();
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => operations.push(finish_operation(core::mem::take(&mut tokens))?),
+            '"' => {
+                let mut token = String::new();
+                for c in chars.by_ref() {
+                    if c == '"' { break };
+                    token.push(c);
+                }
+
+                tokens.push(token);
+            },
+            c if c.is_whitespace() => {},
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != ';' && *c != '"') {
+                    token.push(c);
+                }
+
+                tokens.push(token);
+            },
+        }
+    }
+
+    // the last operation may leave out its semicolon
+    if !tokens.is_empty() {
+        operations.push(finish_operation(tokens)?);
+    }
+
+    Ok(operations)
+}
+
+fn finish_operation(tokens: Vec<String>) -> Result<(String, Vec<String>), FenError> {
+    let mut tokens = tokens.into_iter();
+    let opcode = tokens.next().unwrap_or_default();
+
+    let valid = opcode.starts_with(|c: char| c.is_ascii_alphabetic())
+        && opcode.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(FenError::InvalidOperation(opcode));
+    }
+
+    Ok((opcode, tokens.collect()))
+}
+
+#[test]
+fn test_fen() {
+    for fen in [
+        "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 3 42",
+        "8/8/8/8/8/8/8/K6k w - - 99 150",
+    ] {
+        assert_eq!(Game::from_fen(fen).unwrap().to_fen(), fen);
+    }
+
+    assert_eq!(Game::from_fen("8/8/8/8/8/8/8/K6k w - -").unwrap().to_fen(), "8/8/8/8/8/8/8/K6k w - - 0 1");
+    assert_eq!(Game::from_fen("8/8/8/8/8/8/8/K6k").unwrap_err(), FenError::Missing(FenField::SideToMove));
+    assert_eq!(Game::from_fen("8/8/8/8/8/8/8/K6k w KK -").unwrap_err(), FenError::Invalid(FenField::Castling));
+    assert_eq!(Game::from_fen("8/8/8/8/8/8/8/K6k w - e4").unwrap_err(), FenError::Invalid(FenField::EnPassant));
+    assert_eq!(Game::from_fen("8/8/8/8/8/8/8/K5k w - - x").unwrap_err(), FenError::Invalid(FenField::Pieces));
+    assert_eq!(Game::from_fen("8/8/8/8/8/8/8/K6k w - - 0 x").unwrap_err(), FenError::Invalid(FenField::FullmoveNumber));
+    assert_eq!(Game::from_fen("8/8/8/8/8/8/8/K6K w - - 0 1").unwrap_err(), FenError::IllegalPosition);
+
+    let epd = Game::from_epd(r#"1k1r4/pp1b1R2/3q2pp/4p3/2B5/4Q3/PPP2B2/2K5 b - - bm Qd1+; id "BK.01"; c0 "a; b"; hmvc 7"#).unwrap();
+    assert_eq!(epd.best_moves(), ["Qd1+"]);
+    assert!(epd.avoid_moves().is_empty());
+    assert_eq!(epd.id(), Some("BK.01"));
+    assert_eq!(epd.comment(), Some("a; b"));
+    assert_eq!(epd.game.to_fen(), "1k1r4/pp1b1R2/3q2pp/4p3/2B5/4Q3/PPP2B2/2K5 b - - 7 1");
+    assert!(epd.accepts(epd.game.parse_san("Qd1").unwrap()));
+    assert!(!epd.accepts(epd.game.parse_san("Qe7").unwrap()));
+}
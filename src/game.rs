@@ -1,24 +1,41 @@
-use core::str::FromStr;
-use std::ops::Deref;
-
 #[derive(Clone)]
 pub struct Game {
     board: chess::Board,
     fifty_move_counter: usize,
+    /// Plies played since the start of the game
+    game_ply: usize,
     hash_history: HashHistory,
+    #[cfg(feature = "eval-nnue")]
+    accumulators: crate::nnue::Accumulators,
 }
 
 impl Game {
     pub fn new(board: chess::Board) -> Self {
+        Self::from_parts(board, 0, 0)
+    }
+
+    pub(crate) fn from_parts(board: chess::Board, fifty_move_counter: usize, game_ply: usize) -> Self {
         Self {
+            #[cfg(feature = "eval-nnue")]
+            accumulators: crate::nnue::Accumulators::new(&board),
+            fifty_move_counter,
+            game_ply,
+            hash_history: HashHistory::new(board.get_hash()),
             board,
-            fifty_move_counter: 0,
-            hash_history: HashHistory::new(),
         }
     }
 
     pub fn board(&self) -> &chess::Board { &self.board }
 
+    #[cfg(feature = "eval-nnue")]
+    pub fn accumulators(&self) -> &crate::nnue::Accumulators { &self.accumulators }
+
+    /// Recompute the accumulators from scratch, needed after loading another network
+    #[cfg(feature = "eval-nnue")]
+    pub fn refresh_accumulators(&mut self) {
+        self.accumulators = crate::nnue::Accumulators::new(&self.board);
+    }
+
     pub fn is_capture(&self, mov: chess::ChessMove) -> bool {
         self.board().piece_on(mov.get_dest()).is_some()
     }
@@ -39,10 +56,28 @@ impl Game {
 
         let board = self.board.make_move_new(mov);
 
-        let mut hash_history = self.hash_history.clone();
-        hash_history.push(board.get_hash());
+        // positions before an irreversible move can't repeat
+        let hash_history = if fifty_move_counter == 0 {
+            HashHistory::new(board.get_hash())
+        } else {
+            let mut hash_history = self.hash_history.clone();
+            hash_history.push(board.get_hash());
+            hash_history
+        };
+
+        #[cfg(feature = "eval-nnue")]
+        let mut accumulators = self.accumulators.clone();
+        #[cfg(feature = "eval-nnue")]
+        accumulators.update(&self.board, &board, mov);
 
-        Self { board, fifty_move_counter, hash_history }
+        Self {
+            board,
+            fifty_move_counter,
+            game_ply: self.game_ply + 1,
+            hash_history,
+            #[cfg(feature = "eval-nnue")]
+            accumulators,
+        }
     }
 
     pub fn make_null_move(&self) -> Option<Self> {
@@ -52,29 +87,53 @@ impl Game {
         let mut hash_history = self.hash_history.clone();
         hash_history.push(board.get_hash());
 
-        Some(Self { board, fifty_move_counter, hash_history })
+        Some(Self {
+            board,
+            fifty_move_counter,
+            game_ply: self.game_ply + 1,
+            hash_history,
+            #[cfg(feature = "eval-nnue")]
+            accumulators: self.accumulators.clone(),
+        })
     }
 
+    /// Whether the game is drawn by threefold repetition or the fifty move rule
     pub fn can_declare_draw(&self) -> bool {
-        if let Some(last) = self.hash_history.last() {
-            if self.hash_history.iter().filter(|i| last == **i).count() >= 3 {
-                return true;
+        self.is_repetition(0) || self.fifty_move_counter >= 100
+    }
+
+    /// Whether the position is drawn inside a search of `ply` plies from the root: repeating a
+    /// position of the search tree once is enough, while positions played before the root have
+    /// to repeat twice
+    pub fn is_draw(&self, ply: usize) -> bool {
+        self.is_repetition(ply) || self.fifty_move_counter >= 100
+    }
+
+    /// Look for the position among the earlier ones with the same side to move since the last
+    /// irreversible move, as it can't have appeared before that
+    fn is_repetition(&self, ply: usize) -> bool {
+        let hash = self.board.get_hash();
+        let window = self.fifty_move_counter.min(self.hash_history.len() - 1).min(HASH_HISTORY_LEN - 1);
+        let mut seen = 0;
+
+        for back in (4..=window).step_by(2) {
+            if self.hash_history.nth_back(back) == hash {
+                seen += 1;
+
+                if back <= ply || seen >= 2 {
+                    return true;
+                }
             }
         }
 
-        self.fifty_move_counter >= 100
+        false
     }
 
     pub fn history_len(&self) -> usize { self.hash_history.len() }
 
-    pub fn get_fen(&self) -> String {
-        let rfen = self.board().to_string();
-        format!(
-            "{} {} {}",
-            &rfen[..rfen.len() - 4], self.fifty_move_counter,
-            self.hash_history.len() / 2 + 1,
-        )
-    }
+    pub fn fifty_move_counter(&self) -> usize { self.fifty_move_counter }
+
+    pub fn fullmove_number(&self) -> usize { self.game_ply / 2 + 1 }
 
     pub fn visualize(&self, bitboard: chess::BitBoard) {
         for rank in chess::ALL_RANKS.iter().rev() {
@@ -113,6 +172,12 @@ impl Game {
     }
 }
 
+impl core::fmt::Debug for Game {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("Game").field(&self.to_fen()).finish()
+    }
+}
+
 impl core::fmt::Display for Game {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         for rank in chess::ALL_RANKS.iter().rev() {
@@ -154,7 +219,7 @@ impl core::fmt::Display for Game {
         writeln!(f, "└ᵃ──┴ᵇ──┴ᶜ──┴ᵈ──┴ᵉ──┴ᶠ──┴ᵍ──┴ʰ──┘")?;
         writeln!(f)?;
 
-        writeln!(f, "FEN: {}", self.get_fen())?;
+        writeln!(f, "FEN: {}", self.to_fen())?;
         writeln!(f, "Hash: 0x{:016x}", self.board().get_hash())?;
         writeln!(f)?;
 
@@ -165,30 +230,17 @@ impl core::fmt::Display for Game {
     }
 }
 
-impl FromStr for Game {
-    type Err = Box<dyn core::error::Error>;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (rest, moves) = s.rsplit_once(' ').ok_or("")?;
-        let (_, fmc) = rest.rsplit_once(' ').ok_or("")?;
-        let board = chess::Board::from_str(s).map_err(|_| "")?;
-
-        Ok(Self {
-            board,
-            fifty_move_counter: fmc.parse()?,
-            hash_history: HashHistory::unqiue(moves.parse::<usize>()? * 2 - (board.side_to_move() == chess::Color::White) as usize),
-        })
-    }
-}
-
 impl Default for Game {
     fn default() -> Self {
         Self::new(chess::Board::default())
     }
 }
 
-const HASH_HISTORY_LEN: usize = 128;
+/// Repetitions can't reach back past the last irreversible move, and once that is 100 plies ago
+/// the fifty move rule already draws the game, so older positions never have to be looked at
+const HASH_HISTORY_LEN: usize = 102;
 
+/// Hashes of the last positions since the last irreversible move, the current one last
 #[derive(Clone)]
 pub struct HashHistory {
     inner: [u64; HASH_HISTORY_LEN],
@@ -196,20 +248,14 @@ pub struct HashHistory {
 }
 
 impl HashHistory {
-    pub const fn new() -> Self {
-        Self {
-            inner: [0; HASH_HISTORY_LEN],
-            len: 0,
-        }
-    }
+    pub const fn new(hash: u64) -> Self {
+        let mut inner = [0; HASH_HISTORY_LEN];
+        inner[0] = hash;
 
-    pub fn unqiue(len: usize) -> Self {
-        Self {
-            inner: core::array::from_fn(|i| i as u64),
-            len,
-        }
+        Self { inner, len: 1 }
     }
 
+    /// Number of positions pushed, including the ones no longer kept
     pub const fn len(&self) -> usize { self.len }
 
     pub fn push(&mut self, val: u64) {
@@ -217,18 +263,38 @@ impl HashHistory {
         self.len += 1;
     }
 
-    pub fn last(&self) -> Option<u64> {
-        if self.len() == 0 { return None };
-
-        let i = (self.len - 1) % HASH_HISTORY_LEN;
-        Some(self.inner[i])
+    /// Hash of the position `back` plies before the current one, which has to be kept
+    pub fn nth_back(&self, back: usize) -> u64 {
+        debug_assert!(back < self.len.min(HASH_HISTORY_LEN));
+        self.inner[(self.len - 1 - back) % HASH_HISTORY_LEN]
     }
 }
 
-impl Deref for HashHistory {
-    type Target = [u64];
+#[test]
+fn test_repetition() {
+    use core::str::FromStr;
 
-    fn deref(&self) -> &Self::Target {
-        &self.inner[..self.len().min(HASH_HISTORY_LEN)]
+    let play = |game: Game, moves: &[&str]| moves.iter().fold(game, |game, m| {
+        let (src, dst) = m.split_at(2);
+        game.make_move(chess::ChessMove::new(chess::Square::from_str(src).unwrap(), chess::Square::from_str(dst).unwrap(), None))
+    });
+
+    // a long shuffle, none of which counts after the pawn move
+    let mut game = Game::default();
+    for _ in 0..50 {
+        game = play(game, &["g1f3", "g8f6", "f3g1", "f6g8"]);
     }
+    assert_eq!(game.history_len(), 201);
+    game = play(game, &["e2e4"]);
+    assert_eq!(game.history_len(), 1);
+
+    for (i, m) in ["g8f6", "g1f3", "f6g8", "f3g1"].iter().cycle().take(8).enumerate() {
+        game = play(game, &[m]);
+
+        let plies = i + 1;
+        assert_eq!(game.is_draw(4), plies >= 4, "{plies}");
+        assert_eq!(game.can_declare_draw(), plies >= 8, "{plies}");
+    }
+
+    assert_eq!(game.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 8 105");
 }
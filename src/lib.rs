@@ -1,5 +1,10 @@
 pub use eval::{Eval, evaluate_static};
+#[cfg(feature = "eval-pesto")]
+pub use eval::{evaluate_pesto, pesto_params, pesto_trace, PestoTrace, PESTO_PARAMS};
+pub use fen::{Epd, FenError};
 pub use game::Game;
+pub use notation::{parse_uci_move, MoveError};
+pub use pgn::{PgnError, PgnGame, PgnMove};
 pub use see::see;
 
 use std::time::*;
@@ -9,17 +14,29 @@ use sync::*;
 
 use parking_lot::{Condvar, Mutex, RwLock};
 
+pub mod book;
 mod debug;
 mod eval;
+pub mod fen;
 pub mod game;
 mod line;
 mod move_order;
 mod node;
+pub mod notation;
+#[cfg(feature = "eval-nnue")]
+pub mod nnue;
+mod perft;
+pub mod pgn;
 mod search;
 mod see;
 mod shared_table;
 mod sync;
+mod tablebase;
 mod trans_table;
+pub mod tunables;
+
+#[cfg(not(any(feature = "eval-nnue", feature = "eval-pesto")))]
+compile_error!("either the `eval-nnue` or the `eval-pesto` feature has to be enabled");
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -27,13 +44,21 @@ pub struct Engine {
     pub game: RwLock<Game>,
     trans_table: trans_table::TransTable,
 
-    time_ref: Instant,
+    time_ref: RwLock<Instant>,
     soft_time_bound: Duration,
     hard_time_bound: Duration,
     can_time_out: AtomicBool,
+    stop: AtomicBool,
+    pondering: AtomicBool,
 
     debug: debug::DebugStats,
 
+    limits: SearchLimits,
+    multi_pv: usize,
+    lines: Mutex<Vec<(chess::ChessMove, Eval)>>,
+    syzygy: Option<tablebase::Syzygy>,
+    tb_hits: AtomicUsize,
+
     smp_prev: Mutex<Eval>,
     smp_start: Condvar,
     smp_abort: CondBarrier,
@@ -41,14 +66,60 @@ pub struct Engine {
     total_nodes_searched: AtomicUsize,
 
     smp_count: usize,
+    /// Move ordering heuristics of every thread, the main one first
+    thread_data: Box<[ThreadData]>,
+}
+
+/// Heuristics of one search thread, owned by the engine so that they are kept between searches
+struct ThreadData {
+    hist_table: move_order::HistoryTable,
+    countermove: move_order::CountermoveTable,
+    /// Continuation history after the moves one and two plies ago
+    cont_hist: [move_order::ContinuationTable; 2],
+    capt_hist: move_order::CaptureHistoryTable,
+}
+
+impl ThreadData {
+    fn new() -> Self {
+        Self {
+            hist_table: move_order::ButterflyTable::new(),
+            countermove: move_order::CountermoveTable::new(),
+            cont_hist: [move_order::ContinuationTable::new(), move_order::ContinuationTable::new()],
+            capt_hist: move_order::CaptureHistoryTable::new(),
+        }
+    }
+
+    fn clear(&self) {
+        self.hist_table.clear();
+        self.countermove.clear();
+        self.cont_hist.iter().for_each(move_order::ContinuationTable::clear);
+        self.capt_hist.clear();
+    }
+
+    /// Called before every search, while no thread is using the heuristics
+    fn new_search(&self) {
+        #[cfg(feature = "moveord-hist-age")]
+        {
+            self.hist_table.age();
+            self.cont_hist.iter().for_each(move_order::ContinuationTable::age);
+            self.capt_hist.age();
+        }
+    }
 }
 
 pub(crate) struct SmpThread<'a, const MAIN: bool = false> {
     engine: &'a Engine,
     index: usize,
 
-    hist_table: move_order::HistoryTable,
-    countermove: move_order::CountermoveTable,
+    hist_table: &'a move_order::HistoryTable,
+    countermove: &'a move_order::CountermoveTable,
+    cont_hist: &'a [move_order::ContinuationTable; 2],
+    capt_hist: &'a move_order::CaptureHistoryTable,
+
+    /// Root moves to skip, used to find the next best line in MultiPV mode
+    root_excluded: Vec<chess::ChessMove>,
+    /// Depth of the iteration being searched, which bounds how far check extensions reach
+    root_depth: usize,
 
     nodes_searched: usize,
 }
@@ -57,15 +128,23 @@ impl Engine {
     pub fn new(game: Game, hash_size_bytes: usize) -> Self {
         Self {
             game: RwLock::new(game),
-            trans_table: trans_table::TransTable::new(hash_size_bytes / trans_table::TransTable::entry_size()),
+            trans_table: trans_table::TransTable::new(hash_size_bytes, 1),
 
-            time_ref: Instant::now(),
+            time_ref: RwLock::new(Instant::now()),
             soft_time_bound: Duration::default(),
             hard_time_bound: Duration::default(),
             can_time_out: AtomicBool::new(true),
+            stop: AtomicBool::new(false),
+            pondering: AtomicBool::new(false),
 
             debug: debug::DebugStats::default(),
 
+            limits: SearchLimits::default(),
+            multi_pv: 1,
+            lines: Mutex::new(Vec::new()),
+            syzygy: None,
+            tb_hits: AtomicUsize::new(0),
+
             smp_prev: Mutex::new(Eval(0)),
             smp_start: Condvar::new(),
             smp_abort: CondBarrier::new(1),
@@ -73,6 +152,7 @@ impl Engine {
             total_nodes_searched: AtomicUsize::new(0),
 
             smp_count: 0,
+            thread_data: Box::new([ThreadData::new()]),
         }
     }
 
@@ -98,8 +178,13 @@ impl Engine {
             engine: self,
             index,
 
-            hist_table: move_order::ButterflyTable::new(),
-            countermove: move_order::CountermoveTable::new(),
+            hist_table: &self.thread_data[index].hist_table,
+            countermove: &self.thread_data[index].countermove,
+            cont_hist: &self.thread_data[index].cont_hist,
+            capt_hist: &self.thread_data[index].capt_hist,
+
+            root_excluded: Vec::new(),
+            root_depth: 0,
 
             nodes_searched: 0,
         }
@@ -109,6 +194,7 @@ impl Engine {
         self.smp_abort = CondBarrier::new(smp_count + 1);
         self.smp_exit = CondBarrier::new(smp_count + 1);
         self.smp_count = smp_count;
+        self.thread_data = (0..=smp_count).map(|_| ThreadData::new()).collect();
 
         for index in 1..=smp_count {
             // SAFETY: `Engine` checks that no threads are alive when exiting
@@ -135,12 +221,51 @@ impl Engine {
         self.hard_time_bound = time;
     }
 
+    /// Limits used by the following searches on top of the time control. Root moves that aren't
+    /// legal in the current position are dropped, so if none are left all of them are searched.
+    pub fn set_limits(&mut self, mut limits: SearchLimits) {
+        let board = *self.game.read().board();
+        limits.search_moves.retain(|m| board.legal(*m));
+        self.limits = limits;
+    }
+
     pub fn soft_times_up(&self) -> bool {
-        self.can_time_out.load(Ordering::Relaxed) && self.elapsed() > self.soft_time_bound
+        self.can_time_out.load(Ordering::Relaxed) && (self.stopped() || (!self.pondering() && self.elapsed() > self.soft_time_bound))
     }
 
     pub fn hard_times_up(&self) -> bool {
-        self.can_time_out.load(Ordering::Relaxed) && self.elapsed() > self.hard_time_bound
+        self.can_time_out.load(Ordering::Relaxed) && (self.stopped() || (!self.pondering() && self.elapsed() > self.hard_time_bound))
+    }
+
+    /// Ask the running search to return as soon as possible. Can be called from any thread, and
+    /// stays in effect until [`Engine::clear_stop`] is called.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn clear_stop(&self) {
+        self.stop.store(false, Ordering::Relaxed);
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    /// While pondering the search ignores its time bounds and only ends by [`Engine::stop`] or
+    /// [`Engine::ponderhit`].
+    pub fn set_pondering(&self, pondering: bool) {
+        self.pondering.store(pondering, Ordering::Relaxed);
+    }
+
+    pub fn pondering(&self) -> bool {
+        self.pondering.load(Ordering::Relaxed)
+    }
+
+    /// The opponent played the expected move, so continue the running ponder search under the
+    /// normal time control, starting the clock from now.
+    pub fn ponderhit(&self) {
+        *self.time_ref.write() = Instant::now();
+        self.pondering.store(false, Ordering::Relaxed);
     }
 
     pub fn find_pv(&self, best: chess::ChessMove, max: usize) -> Vec<chess::ChessMove> {
@@ -151,7 +276,7 @@ impl Engine {
 
         let mut game = self.game.read().make_move(best);
         while let Some(tte) = self.trans_table.get(game.board().get_hash()) {
-            if tte.next == ChessMove::default() { break }
+            if tte.next == ChessMove::default() || !game.board().legal(tte.next) { break }
 
             pv.push(tte.next);
             game = game.make_move(tte.next);
@@ -162,26 +287,76 @@ impl Engine {
         pv
     }
 
+    /// Number of best root moves to search for, each with its own line and evaluation
+    pub fn set_multi_pv(&mut self, multi_pv: usize) {
+        self.multi_pv = multi_pv.max(1);
+    }
+
+    /// Root moves and evaluations of the last completed iteration, best first
+    pub fn lines(&self) -> Vec<(chess::ChessMove, Eval)> {
+        self.lines.lock().clone()
+    }
+
     pub fn nodes(&self) -> usize {
         self.total_nodes_searched.load(Ordering::Relaxed)
     }
 
+    /// Load Syzygy tablebases from the directories in `path`, or unload them if it is empty
+    pub fn set_syzygy_path(&mut self, path: &str) -> std::io::Result<()> {
+        self.syzygy = if path.is_empty() { None } else { Some(tablebase::Syzygy::open(path)?) };
+        Ok(())
+    }
+
+    /// Load the network for [`evaluate_static`] from `path`, or use the embedded one if it is
+    /// empty. The network is shared by every engine in the process.
+    #[cfg(feature = "eval-nnue")]
+    pub fn set_eval_file(&mut self, path: &str) -> std::io::Result<()> {
+        if path.is_empty() {
+            nnue::use_default_network();
+        } else {
+            nnue::load_network(path)?;
+        }
+
+        self.game.write().refresh_accumulators();
+        Ok(())
+    }
+
+    /// Number of tablebase probes that gave a result in the last search
+    pub fn tb_hits(&self) -> usize {
+        self.tb_hits.load(Ordering::Relaxed)
+    }
+
     pub fn elapsed(&self) -> Duration {
-        self.time_ref.elapsed()
+        self.time_ref.read().elapsed()
     }
 
     pub fn tt_size(&self) -> usize { self.trans_table.size() }
 
     pub fn tt_used(&self) -> usize {
-        self.trans_table.filter_count(|e| e.node_type() != node::NodeType::None)
+        let age = self.trans_table.age();
+        self.trans_table.filter_count(|e| e.node_type() != node::NodeType::None && e.age() == age)
     }
 
     pub fn resize_hash(&mut self, hash_size_bytes: usize) {
-        self.trans_table = trans_table::TransTable::new(hash_size_bytes / trans_table::TransTable::entry_size());
+        // free the old table first, so both are never allocated at once
+        self.trans_table = trans_table::TransTable::new(0, 1);
+        self.trans_table = trans_table::TransTable::new(hash_size_bytes, self.smp_count + 1);
     }
 
+    /// Clear the hash table, using as many threads as the search does
     pub fn clear_hash(&mut self) {
-        self.trans_table.clear();
+        self.trans_table.clear(self.smp_count + 1);
+    }
+
+    /// Forget everything learned from the previous game: the hash table and the move ordering
+    /// heuristics of every thread
+    pub fn new_game(&mut self) {
+        self.clear_hash();
+        self.lines.get_mut().clear();
+
+        for data in self.thread_data.iter() {
+            data.clear();
+        }
     }
 
     pub fn dump_debug(&self) {
@@ -211,6 +386,32 @@ impl<const MAIN: bool> core::ops::Deref for SmpThread<'_, MAIN> {
     }
 }
 
+/// Extra conditions for ending a search, checked alongside the time control
+#[derive(Debug, Default, Clone)]
+pub struct SearchLimits {
+    /// Don't search deeper than this many plies
+    pub depth: Option<usize>,
+    /// Stop once this many nodes are searched in total
+    pub nodes: Option<usize>,
+    /// Stop once a mate in this many moves is found
+    pub mate: Option<usize>,
+    /// Only consider these root moves, or all of them if empty
+    pub search_moves: Vec<chess::ChessMove>,
+}
+
+impl SearchLimits {
+    pub fn allows_root_move(&self, m: chess::ChessMove) -> bool {
+        self.search_moves.is_empty() || self.search_moves.contains(&m)
+    }
+
+    /// Whether an iteration reaching `depth` with the evaluation `eval` ends the search
+    pub fn reached(&self, depth: usize, nodes: usize, eval: Eval) -> bool {
+        self.depth.is_some_and(|d| depth >= d)
+            || self.nodes.is_some_and(|n| nodes >= n)
+            || self.mate.is_some_and(|m| eval.is_positive_mate() && eval.mate_plies().is_some_and(|p| (p as usize).div_ceil(2) <= m))
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct TimeControl {
     pub time_left: usize,
@@ -1,23 +1,35 @@
 use core::{cell::OnceCell, ops::Deref};
 
-use chess::{Board, ChessMove};
+use chess::{ChessMove, Piece};
 
-use crate::{evaluate_static, Eval};
+use crate::{evaluate_static, Eval, Game};
 
 #[derive(Debug, Clone)]
 pub struct PrevMove<'a> {
     pub mov: ChessMove,
+    /// The piece that made the move, none at the root and for null moves
+    pub piece: Option<Piece>,
     pub static_eval: EvalCell<'a>,
     pub prev_move: Option<&'a Self>,
 }
 
 #[derive(Debug, Clone)]
 pub struct EvalCell<'a> {
-    board: &'a Board,
+    game: &'a Game,
     eval: OnceCell<Eval>,
 }
 
-impl PrevMove<'_> {
+impl<'a> PrevMove<'a> {
+    /// The line of the root position, which has no moves before it
+    pub fn root(game: &'a Game) -> Self {
+        Self {
+            mov: ChessMove::default(),
+            piece: None,
+            static_eval: EvalCell::new(game),
+            prev_move: None,
+        }
+    }
+
     pub fn n_plies_ago(&self, n: usize) -> Option<&Self> {
         let mut at = self;
 
@@ -36,9 +48,9 @@ impl PrevMove<'_> {
 }
 
 impl<'a> EvalCell<'a> {
-    pub fn new(board: &'a Board) -> Self {
+    pub fn new(game: &'a Game) -> Self {
         Self {
-            board,
+            game,
             eval: OnceCell::new(),
         }
     }
@@ -48,6 +60,6 @@ impl Deref for EvalCell<'_> {
     type Target = Eval;
 
     fn deref(&self) -> &Self::Target {
-        self.eval.get_or_init(|| evaluate_static(self.board))
+        self.eval.get_or_init(|| evaluate_static(self.game))
     }
 }
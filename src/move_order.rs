@@ -1,8 +1,9 @@
 use core::cmp::*;
-use crate::{line::PrevMove, trans_table::TransTableEntry, Game};
-use core::cell::UnsafeCell;
+use crate::{line::PrevMove, see, Game};
+use core::cell::{Cell, UnsafeCell};
 use crate::eval::PIECE_VALUE;
-use chess::ChessMove;
+use arrayvec::ArrayVec;
+use chess::{ChessMove, MoveGen, Piece, Square};
 
 pub struct ButterflyTable<T>(UnsafeCell<[T; 64 * 64]>);
 
@@ -53,48 +54,299 @@ impl<T> core::ops::IndexMut<ChessMove> for ButterflyTable<T> {
 
 impl ButterflyTable<isize> {
     pub fn update(&self, m: ChessMove, bonus: isize) {
-        const MAX: isize = 32760;
-        let bonus = bonus.min(MAX).max(-MAX);
-        *self.get_mut(m) += bonus - self[m] * bonus.abs() / MAX;
+        apply_bonus(self.get_mut(m), bonus);
     }
+
+    /// Shrink every score, so that moves that were good in earlier searches can be overtaken
+    pub fn age(&self) {
+        unsafe { (*self.0.get()).iter_mut().for_each(|v| *v /= 2); }
+    }
+}
+
+/// Move a history score towards the bonus, by less the closer it already is to the limit
+fn apply_bonus(score: &mut isize, bonus: isize) {
+    const MAX: isize = 32760;
+    let bonus = bonus.min(MAX).max(-MAX);
+    *score += bonus - *score * bonus.abs() / MAX;
 }
 
 pub type HistoryTable = ButterflyTable<isize>;
-pub type KillerTable = ButterflyTable<isize>;
 pub type CountermoveTable = ButterflyTable<ChessMove>;
 
+/// Scores of the quiet moves that caused cutoffs among the siblings of a node, and the last two
+/// of them
+pub struct KillerTable {
+    scores: ButterflyTable<isize>,
+    moves: Cell<[ChessMove; 2]>,
+}
+
+impl KillerTable {
+    pub fn new() -> Self {
+        Self { scores: ButterflyTable::new(), moves: Cell::new([ChessMove::default(); 2]) }
+    }
+
+    pub fn update(&self, m: ChessMove, bonus: isize) {
+        self.scores.update(m, bonus);
+    }
+
+    /// Remember a quiet move that caused a cutoff, dropping the older of the last two
+    pub fn push(&self, m: ChessMove) {
+        let [first, _] = self.moves.get();
+        if first != m {
+            self.moves.set([m, first]);
+        }
+    }
+
+    /// The last two moves that caused a cutoff, or the default move if there aren't as many
+    pub fn moves(&self) -> [ChessMove; 2] { self.moves.get() }
+}
+
+impl core::ops::Index<ChessMove> for KillerTable {
+    type Output = isize;
+
+    fn index(&self, m: ChessMove) -> &Self::Output {
+        &self.scores[m]
+    }
+}
+
+/// History of quiet moves by the piece and destination of an earlier move, then of the move
+/// itself. Each ply offset of the earlier move has its own table.
+pub struct ContinuationTable(UnsafeCell<Box<[isize]>>);
+
+// SAFETY: same as `ButterflyTable`
+unsafe impl Sync for ContinuationTable {}
+
+impl ContinuationTable {
+    pub fn new() -> Self {
+        // too big to build on the stack of a search thread
+        Self(UnsafeCell::new(vec![0; 6 * 64 * 6 * 64].into_boxed_slice()))
+    }
+
+    pub fn clear(&self) {
+        unsafe { (*self.0.get()).fill(0); }
+    }
+
+    pub fn age(&self) {
+        unsafe { (*self.0.get()).iter_mut().for_each(|v| *v /= 2); }
+    }
+
+    fn get_mut(&self, (prev_piece, prev_dest): (Piece, Square), (piece, dest): (Piece, Square)) -> &mut isize {
+        let prev = prev_piece.to_index() * 64 + prev_dest.to_index();
+        unsafe { &mut (*self.0.get())[prev * 6 * 64 + piece.to_index() * 64 + dest.to_index()] }
+    }
+
+    pub fn get(&self, prev: (Piece, Square), cur: (Piece, Square)) -> isize {
+        *self.get_mut(prev, cur)
+    }
+
+    pub fn update(&self, prev: (Piece, Square), cur: (Piece, Square), bonus: isize) {
+        apply_bonus(self.get_mut(prev, cur), bonus);
+    }
+}
+
+/// History of captures by the capturing piece, its destination and the captured piece
+pub struct CaptureHistoryTable(UnsafeCell<[isize; 6 * 64 * 6]>);
+
+// SAFETY: same as `ButterflyTable`
+unsafe impl Sync for CaptureHistoryTable {}
+
+impl CaptureHistoryTable {
+    pub fn new() -> Self {
+        Self(UnsafeCell::new([0; 6 * 64 * 6]))
+    }
+
+    pub fn clear(&self) {
+        unsafe { (*self.0.get()).fill(0); }
+    }
+
+    pub fn age(&self) {
+        unsafe { (*self.0.get()).iter_mut().for_each(|v| *v /= 2); }
+    }
+
+    fn get_mut(&self, game: &Game, m: ChessMove) -> &mut isize {
+        let board = game.board();
+        // SAFETY: only called with captures
+        let (attacker, victim) = unsafe {
+            (board.piece_on(m.get_source()).unwrap_unchecked(), board.piece_on(m.get_dest()).unwrap_unchecked())
+        };
+
+        unsafe { &mut (*self.0.get())[(attacker.to_index() * 64 + m.get_dest().to_index()) * 6 + victim.to_index()] }
+    }
+
+    pub fn get(&self, game: &Game, m: ChessMove) -> isize {
+        *self.get_mut(game, m)
+    }
+
+    pub fn update(&self, game: &Game, m: ChessMove, bonus: isize) {
+        apply_bonus(self.get_mut(game, m), bonus);
+    }
+}
+
 impl<const MAIN: bool> crate::SmpThread<'_, MAIN> {
-    pub(crate) fn move_score(
-        &self,
-        m: ChessMove,
-        prev_move: &PrevMove,
+    /// Continuation history of a quiet move after the moves one and two plies before
+    pub(crate) fn continuation_history(&self, game: &Game, prev_move: &PrevMove, m: ChessMove) -> i32 {
+        if !cfg!(feature = "moveord-cont-hist") { return 0 };
+
+        // SAFETY: the move is legal, so there is a piece to move
+        let cur = (unsafe { game.board().piece_on(m.get_source()).unwrap_unchecked() }, m.get_dest());
+        self.cont_hist.iter().zip([Some(prev_move), prev_move.n_plies_ago(1)])
+            .filter_map(|(table, prev)| Some(table.get((prev?.piece?, prev?.mov.get_dest()), cur) as i32))
+            .sum()
+    }
+
+    pub(crate) fn capture_history(&self, game: &Game, m: ChessMove) -> i32 {
+        if cfg!(feature = "moveord-capt-hist") { self.capt_hist.get(game, m) as i32 } else { 0 }
+    }
+
+    /// Give a bonus to the continuation history of a quiet move
+    pub(crate) fn update_continuation_history(&self, game: &Game, prev_move: &PrevMove, m: ChessMove, bonus: isize) {
+        if !cfg!(feature = "moveord-cont-hist") { return };
+
+        // SAFETY: the move is legal, so there is a piece to move
+        let cur = (unsafe { game.board().piece_on(m.get_source()).unwrap_unchecked() }, m.get_dest());
+        for (table, prev) in self.cont_hist.iter().zip([Some(prev_move), prev_move.n_plies_ago(1)]) {
+            let Some(prev) = prev else { continue };
+            let Some(piece) = prev.piece else { continue };
+            table.update((piece, prev.mov.get_dest()), cur, bonus);
+        }
+    }
+}
+
+/// Stages of [`MovePicker`], in the order they hand out moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    TtMove,
+    GenCaptures,
+    GoodCaptures,
+    Killers(usize),
+    Countermove,
+    GenQuiets,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+/// Hands out the legal moves of a position one at a time, best looking first: the hash move,
+/// captures that don't lose material by MVV-LVA and capture history, the killers and the
+/// countermove, the other moves by history, continuation history and killer scores, then the
+/// losing captures. Each group is only generated and scored once the ones before it are used up,
+/// so nodes that cut off early skip most of the work.
+pub(crate) struct MovePicker {
+    stage: Stage,
+    movegen: Option<MoveGen>,
+    tt_move: ChessMove,
+    countermove: ChessMove,
+    /// Killers handed out, to be skipped later
+    killers: [ChessMove; 2],
+    /// Moves of the current stage with their scores
+    moves: ArrayVec<(ChessMove, i32), 256>,
+    /// Captures put off until the end because they lose material
+    bad_captures: ArrayVec<(ChessMove, i32), 256>,
+}
+
+impl MovePicker {
+    /// `tt_move` and `countermove` may be the default move if there are none, and don't have to
+    /// be legal
+    pub fn new(tt_move: ChessMove, countermove: ChessMove) -> Self {
+        Self {
+            stage: Stage::TtMove,
+            movegen: None,
+            tt_move,
+            countermove,
+            killers: [ChessMove::default(); 2],
+            moves: ArrayVec::new(),
+            bad_captures: ArrayVec::new(),
+        }
+    }
+
+    pub fn next<const MAIN: bool>(
+        &mut self,
+        thread: &crate::SmpThread<'_, MAIN>,
         game: &Game,
-        tte: &Option<TransTableEntry>,
+        prev_move: &PrevMove,
         killer: &KillerTable,
-    ) -> i32 {
-
-        if cfg!(feature = "moveord-hash") && tte.is_some_and(|tte| tte.next == m) {
-            i32::MAX
-        } else if cfg!(feature = "moveord-mvv") && game.is_capture(m) {
-            mvv_lva(game, m) as i32 * 327601
-        } else {
-            #[cfg(feature = "moveord-cmove")]
-            if self.countermove[prev_move.mov] == m {
-                return 327600;
-            }
+    ) -> Option<ChessMove> {
+        let board = game.board();
+
+        loop {
+            match self.stage {
+                Stage::TtMove => {
+                    self.stage = Stage::GenCaptures;
+
+                    // only kept to be skipped later if it is actually handed out
+                    let tt_move = core::mem::take(&mut self.tt_move);
+                    if cfg!(feature = "moveord-hash") && tt_move != ChessMove::default() && board.legal(tt_move) {
+                        self.tt_move = tt_move;
+                        return Some(tt_move);
+                    }
+                },
+                Stage::GenCaptures => {
+                    let movegen = self.movegen.insert(MoveGen::new_legal(board));
+                    movegen.set_iterator_mask(*board.color_combined(!board.side_to_move()));
+
+                    for m in movegen.filter(|m| *m != self.tt_move) {
+                        let mvv = if cfg!(feature = "moveord-mvv") { mvv_lva(game, m) as i32 } else { 0 };
+                        // full history is worth about a pawn of MVV-LVA
+                        self.moves.push((m, mvv * 32 + thread.capture_history(game, m) / 8));
+                    }
+
+                    self.stage = Stage::GoodCaptures;
+                },
+                Stage::GoodCaptures => match pick(&mut self.moves) {
+                    Some((m, score)) if see(game, m) < 0 => self.bad_captures.push((m, score)),
+                    Some((m, _)) => return Some(m),
+                    None => self.stage = Stage::Killers(0),
+                },
+                Stage::Killers(i) => {
+                    self.stage = if i == 0 { Stage::Killers(1) } else { Stage::Countermove };
+
+                    let k = killer.moves()[i];
+                    if cfg!(feature = "moveord-killer") && k != ChessMove::default() && k != self.tt_move && !game.is_capture(k) && board.legal(k) {
+                        self.killers[i] = k;
+                        return Some(k);
+                    }
+                },
+                Stage::Countermove => {
+                    self.stage = Stage::GenQuiets;
+
+                    let cm = core::mem::take(&mut self.countermove);
+                    if cfg!(feature = "moveord-cmove") && cm != ChessMove::default() && cm != self.tt_move && !self.killers.contains(&cm) && !game.is_capture(cm) && board.legal(cm) {
+                        self.countermove = cm;
+                        return Some(cm);
+                    }
+                },
+                Stage::GenQuiets => {
+                    let movegen = self.movegen.as_mut().expect("captures are generated first");
+                    movegen.set_iterator_mask(!chess::EMPTY);
 
-            let hist = if cfg!(feature = "moveord-hist") {
-                self.hist_table[m] as i32
-            } else { 0 };
-            let killer = if cfg!(feature = "moveord-killer") {
-                killer[m] as i32 * 100
-            } else { 0 };
+                    for m in movegen.filter(|m| *m != self.tt_move && *m != self.countermove && !self.killers.contains(m)) {
+                        let hist = if cfg!(feature = "moveord-hist") { thread.hist_table[m] as i32 } else { 0 };
+                        let killer = if cfg!(feature = "moveord-killer") { killer[m] as i32 * 100 } else { 0 };
+                        self.moves.push((m, hist + killer + thread.continuation_history(game, prev_move, m)));
+                    }
 
-            hist + killer
+                    self.stage = Stage::Quiets;
+                },
+                Stage::Quiets => match pick(&mut self.moves) {
+                    Some((m, _)) => return Some(m),
+                    None => self.stage = Stage::BadCaptures,
+                },
+                Stage::BadCaptures => match pick(&mut self.bad_captures) {
+                    Some((m, _)) => return Some(m),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
         }
     }
 }
 
+/// Take out the highest scored move
+fn pick(moves: &mut ArrayVec<(ChessMove, i32), 256>) -> Option<(ChessMove, i32)> {
+    let best = moves.iter().enumerate().max_by_key(|(_, (_, score))| *score)?.0;
+    Some(moves.swap_remove(best))
+}
+
 fn mvv_lva(game: &Game, m: ChessMove) -> i16 {
     const P: i16 = PIECE_VALUE[0];
     const N: i16 = PIECE_VALUE[1];
@@ -118,3 +370,38 @@ fn mvv_lva(game: &Game, m: ChessMove) -> i16 {
 
     MVV_LVA[aggressor][victim]
 }
+
+#[test]
+fn test_move_picker() {
+    let engine = crate::Engine::new(Game::default(), 1 << 20);
+    let thread = engine.new_thread::<true>(0);
+    let start = Game::default();
+    let root = PrevMove::root(&start);
+
+    for fen in [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "4k3/8/8/8/8/8/8/4K2q w - - 0 1",
+    ] {
+        let game = Game::from_fen(fen).unwrap();
+        let legal = MoveGen::new_legal(game.board()).collect::<Vec<_>>();
+
+        // the hash move comes first, an illegal countermove never
+        let tt_move = legal[legal.len() / 2];
+        // and the killer before the other quiet moves
+        let killer = KillerTable::new();
+        let quiet = *legal.iter().rev().find(|m| !game.is_capture(**m) && **m != tt_move).unwrap();
+        killer.push(quiet);
+        let mut picker = MovePicker::new(tt_move, crate::parse_uci_move("a1h8").unwrap());
+
+        let mut picked = Vec::new();
+        while let Some(m) = picker.next(&thread, &game, &root, &killer) {
+            picked.push(m);
+        }
+
+        assert_eq!(picked[0], tt_move);
+        assert_eq!(picked[1..].iter().find(|m| !game.is_capture(**m)), Some(&quiet));
+        assert_eq!(picked.len(), legal.len());
+        assert!(legal.iter().all(|m| picked.contains(m)));
+    }
+}
@@ -0,0 +1,178 @@
+//! Efficiently updatable neural network evaluation, used when the `eval-nnue` feature is on.
+//!
+//! The network is a `(768 -> HIDDEN)x2 -> 1` perceptron with clipped ReLU activation, stored in
+//! the raw little endian `i16` layout that `bullet` saves quantised networks in. The embedded
+//! default network is only a stand-in made from the PeSTO tables (without tapering) by
+//! `nets/gen_default.py`, so load a trained one with [`load_network`].
+
+use core::sync::atomic::{AtomicPtr, Ordering};
+use std::path::Path;
+
+use chess::{BitBoard, Board, Color};
+
+use crate::Eval;
+
+pub const HIDDEN: usize = 128;
+
+const QA: i32 = 255;
+const QB: i32 = 64;
+const SCALE: i32 = 400;
+
+#[repr(C)]
+pub struct Network {
+    feature_weights: [Accumulator; 768],
+    feature_bias: Accumulator,
+    /// Weights for the accumulator of the side to move, then for the other one
+    output_weights: [Accumulator; 2],
+    output_bias: i16,
+}
+
+// networks are copied into memory as they are, which `repr(C)` keeps the same as the file apart
+// from the byte order
+#[cfg(not(target_endian = "little"))]
+compile_error!("the `eval-nnue` feature needs a little endian target");
+
+static DEFAULT: Network = unsafe { core::mem::transmute(*include_bytes!("../nets/default.nnue")) };
+static NETWORK: AtomicPtr<Network> = AtomicPtr::new(&raw const DEFAULT as *mut Network);
+
+#[inline]
+fn network() -> &'static Network {
+    // SAFETY: only ever points to `DEFAULT` or a leaked network
+    unsafe { &*NETWORK.load(Ordering::Acquire) }
+}
+
+/// Load the network to evaluate with in the whole process. Files may be longer than the network
+/// since `bullet` pads them. Positions made before this have to be refreshed with
+/// [`Accumulators::new`].
+pub fn load_network<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    let bytes = std::fs::read(path)?;
+    if bytes.len() < size_of::<Network>() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "network file is too small"));
+    }
+
+    let mut network = Box::<Network>::new_uninit();
+    // SAFETY: every bit pattern is a valid network, and the file is at least as long as one
+    let network = unsafe {
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), network.as_mut_ptr().cast::<u8>(), size_of::<Network>());
+        network.assume_init()
+    };
+
+    // replaced networks are leaked, since positions of other threads might still use them
+    NETWORK.store(Box::leak(network), Ordering::Release);
+    Ok(())
+}
+
+/// Go back to the embedded network
+pub fn use_default_network() {
+    NETWORK.store(&raw const DEFAULT as *mut Network, Ordering::Release);
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Accumulator {
+    vals: [i16; HIDDEN],
+}
+
+impl Accumulator {
+    #[inline]
+    fn add(&mut self, feature: usize, net: &Network) {
+        for (v, w) in self.vals.iter_mut().zip(&net.feature_weights[feature].vals) {
+            *v += *w;
+        }
+    }
+
+    #[inline]
+    fn sub(&mut self, feature: usize, net: &Network) {
+        for (v, w) in self.vals.iter_mut().zip(&net.feature_weights[feature].vals) {
+            *v -= *w;
+        }
+    }
+
+    #[inline]
+    fn output(&self, weights: &Self) -> i32 {
+        self.vals.iter().zip(&weights.vals).map(|(v, w)| (*v as i32).clamp(0, QA) * *w as i32).sum()
+    }
+}
+
+/// The first layer of the network from both white's and black's view
+#[derive(Clone, PartialEq, Eq)]
+pub struct Accumulators([Accumulator; 2]);
+
+impl Accumulators {
+    pub fn new(board: &Board) -> Self {
+        let net = network();
+        let mut acc = Self([net.feature_bias; 2]);
+
+        for square in *board.combined() {
+            acc.toggle(board, square, true, net);
+        }
+
+        acc
+    }
+
+    /// Update from `old` to `new` by only looking at the squares that changed
+    pub fn update(&mut self, old: &Board, new: &Board, mov: chess::ChessMove) {
+        let net = network();
+        let changed = (old.combined() ^ new.combined())
+            | BitBoard::from_square(mov.get_source())
+            | BitBoard::from_square(mov.get_dest());
+
+        for square in changed {
+            if old.piece_on(square) != new.piece_on(square) || old.color_on(square) != new.color_on(square) {
+                if old.piece_on(square).is_some() { self.toggle(old, square, false, net) };
+                if new.piece_on(square).is_some() { self.toggle(new, square, true, net) };
+            }
+        }
+    }
+
+    #[inline]
+    fn toggle(&mut self, board: &Board, square: chess::Square, add: bool, net: &Network) {
+        // SAFETY: only called for squares with a piece on it
+        let piece = unsafe { board.piece_on(square).unwrap_unchecked() };
+        let color = unsafe { board.color_on(square).unwrap_unchecked() };
+
+        for view in chess::ALL_COLORS {
+            let square = square.to_index() ^ (0b111_000 * (view == Color::Black) as usize);
+            let feature = 384 * (color != view) as usize + 64 * piece.to_index() + square;
+
+            if add {
+                self.0[view.to_index()].add(feature, net);
+            } else {
+                self.0[view.to_index()].sub(feature, net);
+            }
+        }
+    }
+
+    pub fn evaluate(&self, stm: Color) -> Eval {
+        let net = network();
+        let out = self.0[stm.to_index()].output(&net.output_weights[0])
+            + self.0[(!stm).to_index()].output(&net.output_weights[1])
+            + net.output_bias as i32;
+
+        let max = Eval::TB_WIN.0 as i64 - 1;
+        Eval((out as i64 * SCALE as i64 / (QA * QB) as i64).clamp(-max, max) as i16)
+    }
+}
+
+#[test]
+fn test_incremental_update() {
+    use core::str::FromStr;
+
+    let mut rng = fastrand::Rng::with_seed(0);
+
+    for fen in [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    ] {
+        let mut game = crate::Game::from_str(fen).unwrap();
+
+        for _ in 0..100 {
+            let moves = chess::MoveGen::new_legal(game.board()).collect::<Vec<_>>();
+            if moves.is_empty() { break };
+
+            game = game.make_move(moves[rng.usize(..moves.len())]);
+            assert!(*game.accumulators() == Accumulators::new(game.board()), "{}", game.to_fen());
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Move notation: coordinate moves as used by UCI and Lichess, and SAN.
+
+use core::fmt;
+
+use chess::{BoardStatus, ChessMove, MoveGen, Piece, Square};
+
+use crate::Game;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoveError {
+    /// Not a move in the notation at all
+    Invalid(String),
+    /// Well formed, but no legal move of the position matches
+    Illegal(String),
+    /// SAN that more than one legal move matches
+    Ambiguous(String),
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(m) => write!(f, "invalid move `{m}`"),
+            Self::Illegal(m) => write!(f, "illegal move `{m}`"),
+            Self::Ambiguous(m) => write!(f, "ambiguous move `{m}`"),
+        }
+    }
+}
+
+impl core::error::Error for MoveError {}
+
+/// Read a coordinate move like `e2e4` or `e7e8q`, without checking if it is legal anywhere
+pub fn parse_uci_move(m: &str) -> Result<ChessMove, MoveError> {
+    let invalid = || MoveError::Invalid(m.to_string());
+    if !(4..=5).contains(&m.len()) || !m.is_ascii() {
+        return Err(invalid());
+    }
+
+    let src = parse_square(&m[0..2]).ok_or_else(invalid)?;
+    let dst = parse_square(&m[2..4]).ok_or_else(invalid)?;
+    let promotion = match m.as_bytes().get(4) {
+        None => None,
+        Some(b'n') => Some(Piece::Knight),
+        Some(b'b') => Some(Piece::Bishop),
+        Some(b'r') => Some(Piece::Rook),
+        Some(b'q') => Some(Piece::Queen),
+        Some(_) => return Err(invalid()),
+    };
+
+    Ok(ChessMove::new(src, dst, promotion))
+}
+
+fn parse_square(s: &str) -> Option<Square> {
+    match s.as_bytes() {
+        [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Some(Square::new((rank - b'1') << 3 | (file - b'a'))),
+        _ => None,
+    }
+}
+
+fn piece_letter(piece: Piece) -> char {
+    match piece {
+        Piece::Pawn => 'P',
+        Piece::Knight => 'N',
+        Piece::Bishop => 'B',
+        Piece::Rook => 'R',
+        Piece::Queen => 'Q',
+        Piece::King => 'K',
+    }
+}
+
+fn piece_from_letter(c: char) -> Option<Piece> {
+    chess::ALL_PIECES.into_iter().find(|p| piece_letter(*p) == c)
+}
+
+impl Game {
+    /// Read a coordinate move that has to be legal in this position
+    pub fn parse_uci_move(&self, m: &str) -> Result<ChessMove, MoveError> {
+        let mov = parse_uci_move(m)?;
+        if self.board().legal(mov) { Ok(mov) } else { Err(MoveError::Illegal(m.to_string())) }
+    }
+
+    /// Write a legal move in SAN, with the check or mate suffix
+    pub fn to_san(&self, mov: ChessMove) -> String {
+        let board = self.board();
+        let (src, dst) = (mov.get_source(), mov.get_dest());
+        // SAFETY: the move is legal, so there is a piece to move
+        let piece = unsafe { board.piece_on(src).unwrap_unchecked() };
+
+        let mut san = if piece == Piece::King && src.get_file().to_index().abs_diff(dst.get_file().to_index()) == 2 {
+            if dst.get_file() == chess::File::G { "O-O" } else { "O-O-O" }.to_string()
+        } else {
+            let capture = board.piece_on(dst).is_some() || (piece == Piece::Pawn && src.get_file() != dst.get_file());
+            let mut san = String::new();
+
+            if piece == Piece::Pawn {
+                if capture { san.push((b'a' + src.get_file().to_index() as u8) as char) };
+            } else {
+                san.push(piece_letter(piece));
+
+                // other pieces of the same kind that could also go there
+                let others = MoveGen::new_legal(board)
+                    .filter(|m| m.get_dest() == dst && m.get_source() != src && board.piece_on(m.get_source()) == Some(piece))
+                    .map(|m| m.get_source())
+                    .collect::<Vec<_>>();
+
+                if !others.is_empty() {
+                    let file = (b'a' + src.get_file().to_index() as u8) as char;
+                    let rank = (b'1' + src.get_rank().to_index() as u8) as char;
+
+                    if others.iter().all(|o| o.get_file() != src.get_file()) {
+                        san.push(file);
+                    } else if others.iter().all(|o| o.get_rank() != src.get_rank()) {
+                        san.push(rank);
+                    } else {
+                        san.push(file);
+                        san.push(rank);
+                    }
+                }
+            }
+
+            if capture { san.push('x') };
+            san += &dst.to_string();
+
+            if let Some(promotion) = mov.get_promotion() {
+                san.push('=');
+                san.push(piece_letter(promotion));
+            }
+
+            san
+        };
+
+        let after = board.make_move_new(mov);
+        if after.status() == BoardStatus::Checkmate {
+            san.push('#');
+        } else if after.checkers().0 != 0 {
+            san.push('+');
+        }
+
+        san
+    }
+
+    /// Write a line of moves played from this position in SAN, separated by spaces
+    pub fn to_san_line(&self, moves: &[ChessMove]) -> String {
+        let mut game = self.clone();
+        let mut line = Vec::with_capacity(moves.len());
+
+        for m in moves {
+            if !game.board().legal(*m) { break };
+
+            line.push(game.to_san(*m));
+            game = game.make_move(*m);
+        }
+
+        line.join(" ")
+    }
+
+    /// Read a move in SAN. Also takes the common sloppy forms: missing or extra disambiguation,
+    /// missing `x` or `=`, and castling with zeros.
+    pub fn parse_san(&self, san: &str) -> Result<ChessMove, MoveError> {
+        let board = self.board();
+        let invalid = || MoveError::Invalid(san.to_string());
+        let s = san.trim_end_matches(['+', '#', '!', '?']);
+
+        let castles = |king_side: bool| MoveGen::new_legal(board).filter(|m| {
+            let (src, dst) = (m.get_source().to_index(), m.get_dest().to_index());
+            board.piece_on(m.get_source()) == Some(Piece::King) && if king_side { dst == src + 2 } else { dst + 2 == src }
+        }).collect::<Vec<_>>();
+
+        let candidates = if matches!(s, "O-O" | "0-0") {
+            castles(true)
+        } else if matches!(s, "O-O-O" | "0-0-0") {
+            castles(false)
+        } else {
+            let (s, promotion) = match s.char_indices().last() {
+                Some((i, c)) if i > 0 && piece_from_letter(c).is_some() => {
+                    (s[..i].trim_end_matches('='), piece_from_letter(c))
+                },
+                _ => (s, None),
+            };
+
+            let (piece, s) = match s.chars().next().and_then(piece_from_letter) {
+                Some(piece) => (piece, &s[1..]),
+                None => (Piece::Pawn, s),
+            };
+
+            let s = s.replace(['x', ':', '-'], "");
+            if s.len() < 2 || !s.is_ascii() { return Err(invalid()) };
+            let (from, dst) = s.split_at(s.len() - 2);
+            let dst = parse_square(dst).ok_or_else(invalid)?;
+
+            let mut from_file = None;
+            let mut from_rank = None;
+            for c in from.bytes() {
+                match c {
+                    b'a'..=b'h' => from_file = Some((c - b'a') as usize),
+                    b'1'..=b'8' => from_rank = Some((c - b'1') as usize),
+                    _ => return Err(invalid()),
+                }
+            }
+
+            MoveGen::new_legal(board).filter(|m| {
+                m.get_dest() == dst
+                    && board.piece_on(m.get_source()) == Some(piece)
+                    && m.get_promotion() == promotion
+                    && from_file.is_none_or(|f| m.get_source().get_file().to_index() == f)
+                    && from_rank.is_none_or(|r| m.get_source().get_rank().to_index() == r)
+            }).collect()
+        };
+
+        match candidates[..] {
+            [mov] => Ok(mov),
+            [] => Err(MoveError::Illegal(san.to_string())),
+            _ => Err(MoveError::Ambiguous(san.to_string())),
+        }
+    }
+}
+
+#[test]
+fn test_san() {
+    let game = Game::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+
+    for (uci, san) in [
+        ("e1g1", "O-O"),
+        ("e1c1", "O-O-O"),
+        ("d5e6", "dxe6"),
+        ("e5f7", "Nxf7"),
+        ("c3b1", "Nb1"),
+        ("e2a6", "Bxa6"),
+        ("f3f6", "Qxf6"),
+        ("a1b1", "Rb1"),
+        ("g2h3", "gxh3"),
+    ] {
+        let mov = game.parse_uci_move(uci).unwrap();
+        assert_eq!(game.to_san(mov), san);
+        assert_eq!(game.parse_san(san), Ok(mov));
+    }
+
+    assert_eq!(game.parse_san("0-0"), game.parse_uci_move("e1g1"));
+    assert_eq!(game.parse_san("Nc3b1"), game.parse_uci_move("c3b1"));
+    assert_eq!(game.parse_san("Ke3"), Err(MoveError::Illegal("Ke3".to_string())));
+    assert_eq!(game.parse_san("Zz9"), Err(MoveError::Invalid("Zz9".to_string())));
+    assert_eq!(game.parse_uci_move("e1e3"), Err(MoveError::Illegal("e1e3".to_string())));
+    assert_eq!(parse_uci_move("e7e8k"), Err(MoveError::Invalid("e7e8k".to_string())));
+
+    // disambiguation by file, rank and both
+    for (fen, uci, san) in [
+        ("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", "b1d2", "Nbd2"),
+        ("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3", "R1a3"),
+        ("4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1", "a1b2", "Qa1b2"),
+    ] {
+        let game = Game::from_fen(fen).unwrap();
+        let mov = game.parse_uci_move(uci).unwrap();
+
+        assert_eq!(game.to_san(mov), san);
+        assert_eq!(game.parse_san(san), Ok(mov));
+        assert_eq!(game.parse_san(&format!("{}{}", &san[..1], &san[san.len() - 2..])), Err(MoveError::Ambiguous(format!("{}{}", &san[..1], &san[san.len() - 2..]))));
+    }
+
+    // promotions, checks and mates
+    let game = Game::from_fen("6k1/1P3ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+    assert_eq!(game.to_san(game.parse_uci_move("b7b8q").unwrap()), "b8=Q#");
+    assert_eq!(game.to_san(game.parse_uci_move("b7b8n").unwrap()), "b8=N");
+    assert_eq!(game.to_san(game.parse_uci_move("a1a8").unwrap()), "Ra8#");
+    assert_eq!(game.parse_san("b8Q"), game.parse_uci_move("b7b8q"));
+    assert_eq!(game.to_san_line(&[game.parse_uci_move("a1a7").unwrap()]), "Ra7");
+}
@@ -0,0 +1,48 @@
+use chess::{ChessMove, MoveGen};
+
+use crate::Game;
+
+impl Game {
+    /// Count the leaf nodes of the legal move tree `depth` plies deep. Every move goes through
+    /// [`Game::make_move`], so this also exercises the state a game carries along.
+    pub fn perft(&self, depth: usize) -> usize {
+        if depth == 0 { return 1 };
+
+        MoveGen::new_legal(self.board())
+            .map(|m| self.make_move(m).perft(depth - 1))
+            .sum()
+    }
+
+    /// [`Game::perft`] split by the root moves
+    pub fn divide(&self, depth: usize) -> Vec<(ChessMove, usize)> {
+        MoveGen::new_legal(self.board())
+            .map(|m| (m, self.make_move(m).perft(depth.saturating_sub(1))))
+            .collect()
+    }
+}
+
+#[test]
+fn test_perft() {
+    use core::str::FromStr;
+
+    for (fen, counts) in [
+        ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &[20, 400, 8902, 197281][..]),
+        // kiwipete
+        ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2039, 97862]),
+        // en passant out of a pin
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43238]),
+        // promotions and castling out of checks
+        ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9467]),
+        ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1486, 62379]),
+        ("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", &[46, 2079, 89890]),
+    ] {
+        let game = Game::from_str(fen).unwrap();
+
+        for (depth, count) in counts.iter().enumerate() {
+            assert_eq!(game.perft(depth + 1), *count, "perft({}) of {fen}", depth + 1);
+        }
+
+        let last = counts.len();
+        assert_eq!(game.divide(last).iter().map(|(_, n)| n).sum::<usize>(), counts[last - 1]);
+    }
+}
@@ -0,0 +1,514 @@
+//! PGN reading and writing, with variations, comments and NAGs.
+
+use core::{fmt, str::FromStr};
+
+use chess::{ChessMove, Color};
+
+use crate::{Eval, FenError, Game, MoveError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgnError {
+    /// The `FEN` tag is broken
+    Fen(FenError),
+    Move(MoveError),
+    /// Unclosed tags, comments or variations, and other text that isn't PGN
+    Syntax(String),
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fen(e) => write!(f, "invalid FEN tag: {e}"),
+            Self::Move(e) => e.fmt(f),
+            Self::Syntax(e) => write!(f, "invalid PGN: {e}"),
+        }
+    }
+}
+
+impl core::error::Error for PgnError {}
+
+impl From<FenError> for PgnError {
+    fn from(e: FenError) -> Self { Self::Fen(e) }
+}
+
+impl From<MoveError> for PgnError {
+    fn from(e: MoveError) -> Self { Self::Move(e) }
+}
+
+/// A game with its tags and annotated main line
+#[derive(Debug, Clone)]
+pub struct PgnGame {
+    /// Tag pairs in the order they are written
+    pub tags: Vec<(String, String)>,
+    /// The position before the first move, which is the `FEN` tag if there is one
+    pub start: Game,
+    /// Comment before the first move
+    pub comment: Option<String>,
+    pub moves: Vec<PgnMove>,
+    /// `1-0`, `0-1`, `1/2-1/2` or `*`
+    pub result: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgnMove {
+    pub mov: ChessMove,
+    /// Numeric annotation glyphs, with `!`, `?` and the like turned into theirs
+    pub nags: Vec<u8>,
+    /// Comment after the move
+    pub comment: Option<String>,
+    /// Lines played instead of this move
+    pub variations: Vec<Variation>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Variation {
+    /// Comment before the first move
+    pub comment: Option<String>,
+    pub moves: Vec<PgnMove>,
+}
+
+impl PgnGame {
+    /// An empty game with the seven tag roster left unknown, and the `SetUp` and `FEN` tags if
+    /// it doesn't start from the start position
+    pub fn new(start: Game) -> Self {
+        let mut tags = ["Event", "Site", "Date", "Round", "White", "Black", "Result"]
+            .map(|name| (name.to_string(), if name == "Date" { "????.??.??" } else { "?" }.to_string()))
+            .to_vec();
+        tags[6].1 = "*".to_string();
+
+        let fen = start.to_fen();
+        if fen != Game::default().to_fen() {
+            tags.push(("SetUp".to_string(), "1".to_string()));
+            tags.push(("FEN".to_string(), fen));
+        }
+
+        Self { tags, start, comment: None, moves: Vec::new(), result: "*".to_string() }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Set a tag, adding it to the end if it isn't there yet
+    pub fn set_tag(&mut self, name: &str, value: impl Into<String>) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.into(),
+            None => self.tags.push((name.to_string(), value.into())),
+        }
+    }
+
+    /// Set the result in both the tag and the movetext
+    pub fn set_result(&mut self, result: &str) {
+        self.set_tag("Result", result);
+        self.result = result.to_string();
+    }
+
+    /// The start position followed by the position after each move of the main line
+    pub fn positions(&self) -> Vec<Game> {
+        let mut positions = Vec::with_capacity(self.moves.len() + 1);
+        positions.push(self.start.clone());
+
+        for m in self.moves.iter() {
+            let last = positions.last().expect("there is always the start position");
+            positions.push(last.make_move(m.mov));
+        }
+
+        positions
+    }
+
+    /// The position at the end of the main line
+    pub fn end(&self) -> Game {
+        self.moves.iter().fold(self.start.clone(), |game, m| game.make_move(m.mov))
+    }
+}
+
+impl PgnMove {
+    pub fn new(mov: ChessMove) -> Self {
+        Self { mov, nags: Vec::new(), comment: None, variations: Vec::new() }
+    }
+
+    /// A move commented with the engine evaluation from the view of the side making it, like
+    /// `{+0.35/12}`
+    pub fn with_eval(mov: ChessMove, eval: Eval, depth: usize) -> Self {
+        let eval = match eval.mate_plies() {
+            Some(plies) => format!("{}M{}/{depth}", if eval.is_positive_mate() { '+' } else { '-' }, (plies + 1) / 2),
+            None => format!("{:+.2}/{depth}", eval.0 as f64 / 100.0),
+        };
+
+        Self { comment: Some(eval), ..Self::new(mov) }
+    }
+
+    /// Read back the evaluation and depth from a comment written like [`Self::with_eval`]
+    pub fn eval(&self) -> Option<(Eval, usize)> {
+        let (eval, depth) = self.comment.as_ref()?.split_whitespace().next()?.split_once('/')?;
+        let depth = depth.parse().ok()?;
+        // mates too far away for an `Eval`, and mating in 0 moves, are rejected
+        let plies = |moves: &str, sub| {
+            moves.parse::<usize>().ok()?.checked_mul(2)?.checked_sub(sub).filter(|p| *p < 0x4000)
+        };
+
+        let eval = if let Some(moves) = eval.strip_prefix("+M") {
+            Eval::mate_in(plies(moves, 1)?)
+        } else if let Some(moves) = eval.strip_prefix("-M") {
+            Eval::mated_in(plies(moves, 0)?)
+        } else {
+            Eval((eval.parse::<f64>().ok()? * 100.0).round() as i16)
+        };
+
+        Some((eval, depth))
+    }
+}
+
+/// Read all games of a PGN file
+pub fn read_games(pgn: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let mut games = Vec::new();
+    let mut reader = GameReader::default();
+
+    for token in tokenize(pgn)? {
+        if matches!(token, Token::Tag(..)) && !reader.lines.is_empty() {
+            games.push(core::mem::take(&mut reader).finish(None)?);
+        }
+
+        if let Some(result) = reader.push(token)? {
+            games.push(core::mem::take(&mut reader).finish(Some(result))?);
+        }
+    }
+
+    if !reader.tags.is_empty() || !reader.lines.is_empty() {
+        games.push(reader.finish(None)?);
+    }
+
+    Ok(games)
+}
+
+impl FromStr for PgnGame {
+    type Err = PgnError;
+
+    /// Read the first game of a PGN file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        read_games(s)?.into_iter().next().ok_or_else(|| PgnError::Syntax("no game".to_string()))
+    }
+}
+
+enum Token<'a> {
+    Tag(String, String),
+    Comment(&'a str),
+    Open,
+    Close,
+    Nag(u8),
+    Symbol(&'a str),
+}
+
+fn tokenize(pgn: &str) -> Result<Vec<Token<'_>>, PgnError> {
+    let unclosed = |what: &str| PgnError::Syntax(format!("unclosed {what}"));
+    let mut tokens = Vec::new();
+    let mut rest = pgn;
+
+    while let Some(c) = rest.chars().next() {
+        let after = &rest[c.len_utf8()..];
+
+        rest = match c {
+            // escaped lines, which also covers the `;` comments
+            '%' | ';' => after.split_once('\n').map_or("", |(_, r)| r),
+            '{' => {
+                let (comment, r) = after.split_once('}').ok_or_else(|| unclosed("comment"))?;
+                tokens.push(Token::Comment(comment.trim()));
+                r
+            },
+            '[' => {
+                let (name, r) = after.trim_start().split_once('"').ok_or_else(|| unclosed("tag"))?;
+
+                let mut value = String::new();
+                let mut chars = r.char_indices();
+                let end = loop {
+                    match chars.next().ok_or_else(|| unclosed("tag"))? {
+                        (_, '\\') => value.extend(chars.next().map(|(_, c)| c)),
+                        (i, '"') => break i,
+                        (_, c) => value.push(c),
+                    }
+                };
+
+                let r = r[end + 1..].trim_start().strip_prefix(']').ok_or_else(|| unclosed("tag"))?;
+                tokens.push(Token::Tag(name.trim().to_string(), value));
+                r
+            },
+            '(' => { tokens.push(Token::Open); after },
+            ')' => { tokens.push(Token::Close); after },
+            '$' => {
+                let len = after.find(|c: char| !c.is_ascii_digit()).unwrap_or(after.len());
+                let nag = after[..len].parse().map_err(|_| PgnError::Syntax(format!("invalid NAG `${}`", &after[..len])))?;
+                tokens.push(Token::Nag(nag));
+                &after[len..]
+            },
+            c if c.is_whitespace() => after,
+            _ => {
+                let len = rest.find(|c: char| c.is_whitespace() || "{}()[];$".contains(c)).unwrap_or(rest.len());
+                tokens.push(Token::Symbol(&rest[..len]));
+                &rest[len..]
+            },
+        };
+    }
+
+    Ok(tokens)
+}
+
+/// A line being read, either the main line or a variation
+struct Line {
+    comment: Option<String>,
+    moves: Vec<PgnMove>,
+    /// The positions before and after the last move
+    prev: Game,
+    game: Game,
+}
+
+impl Line {
+    fn new(game: Game) -> Self {
+        Self { comment: None, moves: Vec::new(), prev: game.clone(), game }
+    }
+}
+
+#[derive(Default)]
+struct GameReader {
+    tags: Vec<(String, String)>,
+    /// The main line and then the variations open inside it, empty before the movetext
+    lines: Vec<Line>,
+}
+
+impl GameReader {
+    /// Returns the result if the token ends the game
+    fn push(&mut self, token: Token<'_>) -> Result<Option<String>, PgnError> {
+        if let Token::Tag(name, value) = token {
+            self.tags.push((name, value));
+            return Ok(None);
+        }
+
+        if self.lines.is_empty() {
+            let start = self.tags.iter()
+                .find(|(name, _)| name == "FEN")
+                .map_or_else(|| Ok(Game::default()), |(_, fen)| Game::from_fen(fen))?;
+            self.lines.push(Line::new(start));
+        }
+
+        let line = self.lines.last_mut().expect("there is always the main line here");
+
+        match token {
+            Token::Tag(..) => unreachable!(),
+            Token::Comment(comment) => {
+                let to = match line.moves.last_mut() {
+                    Some(m) => &mut m.comment,
+                    None => &mut line.comment,
+                };
+
+                match to {
+                    Some(c) => { c.push(' '); c.push_str(comment) },
+                    None => *to = Some(comment.to_string()),
+                }
+            },
+            Token::Open => {
+                if line.moves.is_empty() {
+                    return Err(PgnError::Syntax("variation before any move".to_string()));
+                }
+
+                let prev = line.prev.clone();
+                self.lines.push(Line::new(prev));
+            },
+            Token::Close => {
+                if self.lines.len() < 2 {
+                    return Err(PgnError::Syntax("unopened variation".to_string()));
+                }
+
+                let variation = self.lines.pop().expect("checked above that there are at least 2 lines");
+                let parent = self.lines.last_mut()
+                    .and_then(|line| line.moves.last_mut())
+                    .expect("variations are only opened after a move");
+                parent.variations.push(Variation { comment: variation.comment, moves: variation.moves });
+            },
+            Token::Nag(nag) => match line.moves.last_mut() {
+                Some(m) => m.nags.push(nag),
+                None => return Err(PgnError::Syntax(format!("NAG `${nag}` before any move"))),
+            },
+            Token::Symbol(result @ ("1-0" | "0-1" | "1/2-1/2" | "*")) => {
+                if self.lines.len() > 1 {
+                    return Err(PgnError::Syntax("unclosed variation".to_string()));
+                }
+
+                return Ok(Some(result.to_string()));
+            },
+            Token::Symbol(s) => {
+                // move numbers, which may be stuck to the move
+                let s = match s.rsplit_once('.') {
+                    Some((number, s)) if number.trim_end_matches('.').bytes().all(|c| c.is_ascii_digit()) => s,
+                    _ => s,
+                };
+                if s.is_empty() { return Ok(None) };
+
+                let san = s.trim_end_matches(['!', '?']);
+                let mov = line.game.parse_san(san)?;
+
+                let mut m = PgnMove::new(mov);
+                match &s[san.len()..] {
+                    "" => {},
+                    "!" => m.nags.push(1),
+                    "?" => m.nags.push(2),
+                    "!!" => m.nags.push(3),
+                    "??" => m.nags.push(4),
+                    "!?" => m.nags.push(5),
+                    "?!" => m.nags.push(6),
+                    _ => return Err(PgnError::Syntax(format!("invalid annotation on `{s}`"))),
+                }
+
+                let next = line.game.make_move(mov);
+                line.prev = core::mem::replace(&mut line.game, next);
+                line.moves.push(m);
+            },
+        }
+
+        Ok(None)
+    }
+
+    fn finish(mut self, result: Option<String>) -> Result<PgnGame, PgnError> {
+        if self.lines.len() > 1 {
+            return Err(PgnError::Syntax("unclosed variation".to_string()));
+        }
+
+        let (comment, moves) = self.lines.pop().map_or((None, Vec::new()), |line| (line.comment, line.moves));
+
+        let result = result
+            .or_else(|| self.tags.iter().find(|(name, _)| name == "Result").map(|(_, r)| r.clone()))
+            .unwrap_or_else(|| "*".to_string());
+
+        let fen = self.tags.iter().find(|(name, _)| name == "FEN");
+        let start = fen.map_or_else(|| Ok(Game::default()), |(_, fen)| Game::from_fen(fen))?;
+
+        Ok(PgnGame { tags: self.tags, start, comment, moves, result })
+    }
+}
+
+impl fmt::Display for PgnGame {
+    /// Write the game as PGN with the movetext wrapped at 80 columns, ending with a newline
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.tags.iter() {
+            writeln!(f, "[{name} \"{}\"]", value.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+        writeln!(f)?;
+
+        let mut tokens = Vec::new();
+        write_line(&mut tokens, &self.start, self.comment.as_deref(), &self.moves);
+        tokens.push(self.result.clone());
+
+        let mut width = 0;
+        for token in tokens {
+            if width != 0 && width + 1 + token.len() > 80 {
+                writeln!(f)?;
+                width = 0;
+            } else if width != 0 {
+                f.write_str(" ")?;
+                width += 1;
+            }
+
+            f.write_str(&token)?;
+            width += token.len();
+        }
+
+        writeln!(f)
+    }
+}
+
+fn write_line(tokens: &mut Vec<String>, game: &Game, comment: Option<&str>, moves: &[PgnMove]) {
+    let mut game = game.clone();
+    let mut number = true;
+
+    if let Some(comment) = comment {
+        tokens.push(format!("{{{comment}}}"));
+    }
+
+    for m in moves {
+        if game.board().side_to_move() == Color::White {
+            tokens.push(format!("{}.", game.fullmove_number()));
+        } else if number {
+            tokens.push(format!("{}...", game.fullmove_number()));
+        }
+
+        tokens.push(game.to_san(m.mov));
+        tokens.extend(m.nags.iter().map(|nag| format!("${nag}")));
+        number = false;
+
+        if let Some(comment) = &m.comment {
+            tokens.push(format!("{{{comment}}}"));
+            number = true;
+        }
+
+        for variation in m.variations.iter() {
+            let first = tokens.len();
+            write_line(tokens, &game, variation.comment.as_deref(), &variation.moves);
+            if tokens.len() == first { continue };
+
+            tokens[first].insert(0, '(');
+            tokens.last_mut().expect("the variation wrote at least one token").push(')');
+            number = true;
+        }
+
+        game = game.make_move(m.mov);
+    }
+}
+
+#[test]
+fn test_pgn() {
+    let pgn = r#"
+[Event "Test \"quoted\""]
+[Site "?"]
+[Result "1-0"]
+
+{Start} 1. e4 e5 2. Nf3 $1 {Develops} (2. Bc4? Nf6 (2... Bc5) 3. d3) 2... Nc6 3. Bb5!? a6
+; a comment to the end of the line
+4. Ba4 1-0
+
+[Event "Second"]
+[SetUp "1"]
+[FEN "6k1/1P3ppp/8/8/8/8/8/R5K1 w - - 0 40"]
+
+40. b8=Q# *
+"#;
+
+    let games = read_games(pgn).unwrap();
+    assert_eq!(games.len(), 2);
+
+    let game = &games[0];
+    assert_eq!(game.tag("Event"), Some("Test \"quoted\""));
+    assert_eq!(game.result, "1-0");
+    assert_eq!(game.comment.as_deref(), Some("Start"));
+    assert_eq!(game.moves.len(), 7);
+    assert_eq!(game.moves[2].nags, [1]);
+    assert_eq!(game.moves[2].comment.as_deref(), Some("Develops"));
+    assert_eq!(game.moves[2].variations[0].moves.len(), 3);
+    assert_eq!(game.moves[2].variations[0].moves[0].nags, [2]);
+    assert_eq!(game.moves[2].variations[0].moves[1].variations[0].moves.len(), 1);
+    assert_eq!(game.moves[4].nags, [5]);
+    assert_eq!(game.positions().len(), 8);
+    assert_eq!(game.end().to_fen(), "r1bqkbnr/1ppp1ppp/p1n5/4p3/B3P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 1 4");
+
+    let written = game.to_string();
+    assert!(written.contains(r#"[Event "Test \"quoted\""]"#));
+    assert!(written.ends_with("{Start} 1. e4 e5 2. Nf3 $1 {Develops} (2. Bc4 $2 Nf6 (2... Bc5) 3. d3) 2... Nc6\n3. Bb5 $5 a6 4. Ba4 1-0\n"));
+
+    let again = written.parse::<PgnGame>().unwrap();
+    assert_eq!(again.moves, game.moves);
+    assert_eq!(again.tags, game.tags);
+
+    assert_eq!(games[1].start.fullmove_number(), 40);
+    assert_eq!(games[1].result, "*");
+    assert_eq!(games[1].to_string().lines().last(), Some("40. b8=Q# *"));
+
+    let mut game = PgnGame::new(Game::default());
+    let e4 = game.start.parse_san("e4").unwrap();
+    game.moves.push(PgnMove::with_eval(e4, Eval(35), 12));
+    game.set_result("1/2-1/2");
+    assert_eq!(game.to_string().lines().last(), Some("1. e4 {+0.35/12} 1/2-1/2"));
+    assert_eq!(game.moves[0].eval(), Some((Eval(35), 12)));
+    assert_eq!(PgnMove::with_eval(e4, Eval::mate_in(3), 20).eval(), Some((Eval::mate_in(3), 20)));
+    assert_eq!(PgnMove { comment: Some("+M0/5".to_string()), ..PgnMove::new(e4) }.eval(), None);
+
+    assert!(matches!(read_games("1. e4 (1. d4"), Err(PgnError::Syntax(_))));
+    assert!(matches!(read_games("1. e4 ) e5"), Err(PgnError::Syntax(_))));
+    assert!(matches!(read_games("1. e5"), Err(PgnError::Move(MoveError::Illegal(_)))));
+}
@@ -2,25 +2,41 @@ use core::sync::atomic::Ordering;
 
 use crate::{eval::*, line::{EvalCell, PrevMove}, trans_table::*, *};
 use bound::Bound;
-use chess::{BoardStatus, ChessMove, MoveGen, Piece};
-use move_order::KillerTable;
+use chess::{ChessMove, MoveGen, Piece};
+use move_order::{KillerTable, MovePicker};
 use node::{Cut, NodeType, Pv};
 
 mod bound;
 
+/// Threads add their node counts to the engine's at least this often, so that node limits and
+/// `info nodes` also see the helpers' nodes during an iteration
+const NODES_PUBLISH_INTERVAL: usize = 1024;
+
 impl Engine {
-    pub fn best_move<F: FnMut(&Self, (ChessMove, Eval, usize)) -> bool>(&mut self, mut cont: F) -> (ChessMove, Eval, usize) {
-        self.time_ref = Instant::now();
+    pub fn best_move<F: FnMut(&Self, (ChessMove, Eval, usize)) -> bool>(&self, mut cont: F) -> (ChessMove, Eval, usize) {
+        *self.time_ref.write() = Instant::now();
         self.total_nodes_searched.store(0, Ordering::Relaxed);
+        self.tb_hits.store(0, Ordering::Relaxed);
         self.debug.clear();
+        self.trans_table.new_search();
+        self.thread_data.iter().for_each(ThreadData::new_search);
+
+        if let Some(prev) = self.tablebase_move() {
+            *self.lines.lock() = vec![(prev.0, prev.1)];
+            cont(self, prev);
+            return prev;
+        }
 
         let mut main_thread = self.new_thread::<true>(0);
+        let root_moves = MoveGen::new_legal(self.game.read().board()).filter(|m| self.limits.allows_root_move(*m)).count();
+        let multi_pv = self.multi_pv.min(root_moves).max(1);
 
         let can_time_out = self.can_time_out.swap(false, Ordering::Relaxed);
-        let prev = main_thread.root_search(1, Bound::MIN_MAX);
+        let mut lines = main_thread.search_lines(1, multi_pv, &[]);
         self.can_time_out.store(can_time_out, Ordering::Relaxed);
-        let mut prev = (prev.0, prev.1, 1);
-        if !cont(self, prev) || self.soft_times_up() { return prev };
+        let mut prev = (lines[0].0, lines[0].1, 1);
+        *self.lines.lock() = lines.clone();
+        if !cont(self, prev) || self.soft_times_up() || self.limits.reached(1, self.nodes(), prev.1) { return prev };
 
         *self.smp_prev.lock() = prev.1;
         self.smp_abort.initiate_wait();
@@ -31,12 +47,14 @@ impl Engine {
         }
 
         for depth in 2..=255 {
-            let this = main_thread.root_aspiration(depth, prev.1);
+            let this = main_thread.search_lines(depth, multi_pv, &lines);
 
-            if self.hard_times_up() { break };
+            if main_thread.abort() { break };
 
-            prev = (this.0, this.1, depth);
-            if !cont(self, prev) || self.soft_times_up() { break };
+            lines = this;
+            prev = (lines[0].0, lines[0].1, depth);
+            *self.lines.lock() = lines.clone();
+            if !cont(self, prev) || self.soft_times_up() || self.limits.reached(depth, self.nodes(), prev.1) { break };
         }
 
         self.smp_abort.initiate();
@@ -44,6 +62,48 @@ impl Engine {
     }
 }
 
+impl Engine {
+    /// Pick the root move with DTZ tables instead of searching, if the position is in them. Only
+    /// done for a single line over all root moves, since that is all the tables give.
+    fn tablebase_move(&self) -> Option<(ChessMove, Eval, usize)> {
+        let syzygy = self.syzygy.as_ref().filter(|_| self.limits.search_moves.is_empty() && self.multi_pv == 1)?;
+        let game = self.game.read();
+        if !syzygy.can_probe(&game) {
+            return None;
+        }
+
+        let (best, eval) = syzygy.probe_root(&game)?;
+        self.tb_hits.fetch_add(1, Ordering::Relaxed);
+        Some((best, eval, 1))
+    }
+}
+
+impl SmpThread<'_, true> {
+    /// Find the best `count` root moves by searching again with the ones already found excluded
+    fn search_lines(&mut self, depth: usize, count: usize, prev: &[(ChessMove, Eval)]) -> Vec<(ChessMove, Eval)> {
+        let mut lines = Vec::with_capacity(count);
+
+        for i in 0..count {
+            let line = if depth == 1 {
+                let (mov, eval, _) = self.root_search(depth, Bound::MIN_MAX);
+                (mov, eval)
+            } else {
+                let prev = prev.get(i).or(prev.last()).map_or(Eval(0), |l| l.1);
+                self.root_aspiration(depth, prev)
+            };
+
+            if self.abort() { break };
+
+            self.root_excluded.push(line.0);
+            lines.push(line);
+        }
+
+        self.root_excluded.clear();
+        lines.sort_by_key(|l| core::cmp::Reverse(l.1));
+        lines
+    }
+}
+
 impl SmpThread<'_, false> {
     pub fn start(mut self) {
         while !self.smp_exit.initiated() {
@@ -66,7 +126,7 @@ impl SmpThread<'_, false> {
 
 impl<const MAIN: bool> SmpThread<'_, MAIN> {
     fn root_aspiration(&mut self, depth: usize, prev: Eval) -> (ChessMove, Eval) {
-        let mut delta = 13;
+        let mut delta = tunables::asp_delta() as i16;
         let mut bound = Bound::from_window(prev, delta, delta);
 
         let (mut mov, mut eval, mut nt) = self.root_search(depth, bound);
@@ -94,31 +154,49 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
         depth: usize,
         bound: Bound,
     ) -> (ChessMove, Eval, NodeType) {
-        self.nodes_searched = 0;
-
         let game: Game = self.game.read().clone();
-        let line = PrevMove {
-            mov: ChessMove::default(),
-            static_eval: EvalCell::new(game.board()),
-            prev_move: None,
-        };
+        let line = PrevMove::root(&game);
+        self.root_depth = depth;
 
-        let (next, eval, nt) = self._evaluate_search::<Pv, true>(&line, &game, &KillerTable::new(), depth, 0, bound, false);
+        let (next, eval, nt) = self._evaluate_search::<Pv, true>(&line, &game, &KillerTable::new(), depth, 0, bound, false, ChessMove::default());
 
-        self.store_tt(depth, &game, (next, eval, nt));
-        self.total_nodes_searched.fetch_add(self.nodes_searched, Ordering::Relaxed);
+        // the best move of the root is not known when some are excluded
+        if self.root_excluded.is_empty() && self.limits.search_moves.is_empty() {
+            self.store_tt(depth, 0, &game, (next, eval, nt));
+        }
+        self.publish_nodes();
 
         (next, eval, nt)
     }
 
+    #[inline]
+    fn count_node(&mut self) {
+        self.nodes_searched += 1;
+
+        if self.nodes_searched >= NODES_PUBLISH_INTERVAL {
+            self.publish_nodes();
+        }
+    }
+
+    /// Add the nodes searched since the last call to the engine's count
+    fn publish_nodes(&mut self) {
+        self.total_nodes_searched.fetch_add(self.nodes_searched, Ordering::Relaxed);
+        self.nodes_searched = 0;
+    }
+
     fn abort(&self) -> bool {
         if !MAIN {
             self.smp_abort.initiated()
         } else {
-            self.hard_times_up()
+            self.hard_times_up() || self.out_of_nodes()
         }
     }
 
+    fn out_of_nodes(&self) -> bool {
+        self.can_time_out.load(Ordering::Relaxed)
+            && self.limits.nodes.is_some_and(|n| self.nodes() + self.nodes_searched >= n)
+    }
+
     #[inline]
     fn zw_search<Node: node::Node>(
         &mut self,
@@ -146,30 +224,26 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
         bound: Bound,
         in_zw: bool,
     ) -> Eval {
-        let (next, eval, nt) = self._evaluate_search::<Node, false>(prev_move, game, killer, depth, ply, bound, in_zw);
+        let (next, eval, nt) = self._evaluate_search::<Node, false>(prev_move, game, killer, depth, ply, bound, in_zw, ChessMove::default());
 
-        self.store_tt(depth, game, (next, eval, nt));
+        self.store_tt(depth, ply, game, (next, eval, nt));
 
         eval
     }
 
-    fn store_tt(&self, depth: usize, game: &Game, (next, eval, nt): (ChessMove, Eval, NodeType)) {
+    fn store_tt(&self, depth: usize, ply: usize, game: &Game, (next, eval, nt): (ChessMove, Eval, NodeType)) {
         if nt != NodeType::None && !self.abort() {
-            if let Some(tte) = self.trans_table.get_place(game.board().get_hash()) {
-                if tte.depth as usize > depth {
-                    return;
-                }
-            }
-
             self.trans_table.insert(game.board().get_hash(), TransTableEntry {
                 depth: depth as u8,
-                eval,
+                eval: eval.to_tt(ply),
                 next,
                 flags: TransTableEntry::new_flags(nt),
             });
         }
     }
 
+    /// Search every move but `excluded`, which is [`ChessMove::default()`] except in the
+    /// verification searches of singular extensions
     fn _evaluate_search<Node: node::Node, const ROOT: bool>(
         &mut self,
         prev_move: &PrevMove,
@@ -179,27 +253,25 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
         ply: usize,
         mut bound: Bound,
         in_zw: bool,
+        excluded: ChessMove,
     ) -> (ChessMove, Eval, NodeType) {
+        let singular_search = excluded != ChessMove::default();
         let in_check = game.board().checkers().0 != 0;
 
-        if game.can_declare_draw() {
+        // a move has to be found at the root even if the game is already drawn
+        if !ROOT && game.is_draw(ply) {
             return (ChessMove::default(), Eval(0), NodeType::None);
         }
 
-        match game.board().status() {
-            BoardStatus::Ongoing => {},
-            BoardStatus::Checkmate => return (ChessMove::default(), -Eval::M0, NodeType::None),
-            BoardStatus::Stalemate => return (ChessMove::default(), Eval(0), NodeType::None),
-        }
-
         if self.abort() {
             return (ChessMove::default(), Eval(0), NodeType::None);
         }
 
+        // the entry is of the whole position, not of the one without the excluded move
         #[cfg(feature = "search-ttc")]
-        if !Node::PV {
+        if !Node::PV && !singular_search {
             if let Some(trans) = self.trans_table.get(game.board().get_hash()) {
-                let eval = trans.eval;
+                let eval = Eval::from_tt(trans.eval, ply);
                 let node_type = trans.node_type();
 
                 if trans.depth as usize >= depth && (node_type == NodeType::Pv
@@ -210,16 +282,27 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
             }
         }
 
+        // tablebase probes are only exact right after a capture or pawn move
+        if !ROOT && game.fifty_move_counter() == 0 {
+            if let Some(eval) = self.syzygy.as_ref().filter(|tb| tb.can_probe(game)).and_then(|tb| tb.probe_wdl(game)) {
+                self.tb_hits.fetch_add(1, Ordering::Relaxed);
+
+                if eval == Eval(0) || (eval > Eval(0) && eval >= bound.beta) || (eval < Eval(0) && eval <= bound.alpha) {
+                    return (ChessMove::default(), eval, NodeType::None);
+                }
+            }
+        }
+
         if depth == 0 {
-            let (eval, nt) = self._quiescence_search(game, bound);
+            let (eval, nt) = self._quiescence_search(game, ply, bound);
             return (ChessMove::default(), eval, nt);
         }
 
         // reversed futility pruning (aka: static null move)
         #[cfg(feature = "search-rfp")]
         if !Node::PV && !in_check && depth <= 2 && !bound.beta.is_mate() {
-            let eval = evaluate_static(game.board());
-            let margin = 120 * depth as i16;
+            let eval = evaluate_static(game);
+            let margin = tunables::rfp_margin() as i16 * depth as i16;
 
             if eval - margin >= bound.beta {
                 // return (ChessMove::default(), Eval(((eval.0 as i32 + beta.0 as i32) / 2) as i16), NodeType::None);
@@ -231,9 +314,9 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
 
         // internal iterative reductions
         #[cfg(feature = "search-iir")]
-        if !ROOT && depth >= 4 && self.trans_table.get(game.board().get_hash()).is_none() {
-            let low = self._evaluate_search::<Node, ROOT>(prev_move, game, &killer, depth / 4, ply, bound, false);
-            self.store_tt(depth / 4, game, low);
+        if !ROOT && !singular_search && depth >= 4 && self.trans_table.get(game.board().get_hash()).is_none() {
+            let low = self._evaluate_search::<Node, ROOT>(prev_move, game, &killer, depth / 4, ply, bound, false, excluded);
+            self.store_tt(depth / 4, ply, game, low);
 
             if low.1 <= bound.alpha {
                 return (low.0, low.1, NodeType::None);
@@ -242,7 +325,7 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
 
         // null move pruning
         #[cfg(feature = "search-nmp")]
-        if !Node::PV && !in_check && depth >= 4 && (
+        if !Node::PV && !in_check && !singular_search && depth >= 4 && (
             game.board().pieces(Piece::Knight).0 != 0 ||
             game.board().pieces(Piece::Bishop).0 != 0 ||
             game.board().pieces(Piece::Rook).0 != 0 ||
@@ -251,43 +334,95 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
             let game = game.make_null_move().unwrap();
             let line = PrevMove {
                 mov: prev_move.mov,
-                static_eval: EvalCell::new(game.board()),
+                piece: None,
+                static_eval: EvalCell::new(&game),
                 prev_move: Some(prev_move),
             };
 
-            let r = 3 + depth / 3;
+            let r = tunables::nmp_base() as usize + depth / tunables::nmp_div() as usize;
             let eval = -self.zw_search::<Cut>(&line, &game, &killer, depth - r, ply + 1, 1 - bound.beta);
 
             if eval >= bound.beta {
-                return (ChessMove::default(), eval.incr_mate(), NodeType::None);
+                // don't trust mate scores from a null move
+                let eval = if eval.is_mate() { bound.beta } else { eval };
+                return (ChessMove::default(), eval, NodeType::None);
+            }
+        }
+
+        // probcut: a good capture that beats beta by a margin in a shallow search would most
+        // likely beat beta in a full one too
+        #[cfg(feature = "search-probcut")]
+        if !Node::PV && !in_check && !singular_search && depth >= 5 && !bound.beta.is_mate() {
+            let probcut_beta = bound.beta + tunables::probcut_margin() as i16;
+            let see_threshold = probcut_beta.0 as i32 - prev_move.static_eval.0 as i32;
+
+            for m in MoveGen::new_legal(game.board()) {
+                if !game.is_capture(m) || (see(game, m) as i32) < see_threshold { continue };
+
+                let piece = game.board().piece_on(m.get_source());
+                let game = game.make_move(m);
+                let line = PrevMove {
+                    mov: m,
+                    piece,
+                    static_eval: EvalCell::new(&game),
+                    prev_move: Some(prev_move),
+                };
+
+                let eval = -self.zw_search::<Node::Zw>(&line, &game, &killer, depth - 4, ply + 1, 1 - probcut_beta);
+                self.count_node();
+
+                if eval >= probcut_beta {
+                    return (m, eval, NodeType::None);
+                }
             }
         }
 
         // check if late move pruning is applicable
         let can_lmp = !Node::PV && !in_check;
-        let lmp_threshold = 4 + 2 * depth * depth;
+        let lmp_threshold = tunables::lmp_base() as usize + tunables::lmp_mul() as usize * depth * depth;
 
         // check if futility pruning is applicable
-        let f_margin = 150 * depth as i16;
+        let f_margin = tunables::fp_margin() as i16 * depth as i16;
         let can_f_prune = can_lmp
             && depth <= 2
             && *prev_move.static_eval + f_margin <= bound.alpha;
 
         let tte = self.trans_table.get(game.board().get_hash());
 
-        let mut moves = MoveGen::new_legal(game.board())
-            .map(|m| (m, self.move_score(m, prev_move, game, &tte, &p_killer)))
-            .collect::<arrayvec::ArrayVec<_, 256>>();
-        moves.sort_unstable_by_key(|i| -i.1);
-        if ROOT && !MAIN {
-            let len = moves.len();
-            moves.rotate_left((self.index / 2) % len);
+        let mut picker = MovePicker::new(tte.map_or(ChessMove::default(), |tte| tte.next), self.countermove[prev_move.mov]);
+
+        // the root needs all of its moves up front to leave some out and to give the helper
+        // threads different orders
+        let mut root_moves = arrayvec::ArrayVec::<_, 256>::new();
+        if ROOT {
+            while let Some(m) = picker.next(self, game, prev_move, p_killer) {
+                root_moves.push(m);
+            }
+
+            if root_moves.is_empty() {
+                return (ChessMove::default(), if in_check { Eval::mated_in(ply) } else { Eval(0) }, NodeType::None);
+            }
+
+            root_moves.retain(|m| !self.root_excluded.contains(m) && self.limits.allows_root_move(*m));
+            if !MAIN && !root_moves.is_empty() {
+                let len = root_moves.len();
+                root_moves.rotate_left((self.index / 2) % len);
+            }
         }
+        let mut root_moves = root_moves.into_iter();
 
         let mut best = (ChessMove::default(), Eval::MIN);
         let mut children_searched = 0;
+        // the excluded move is legal even though it is not searched
+        let mut any_legal = ROOT || singular_search;
+        // moves searched before the current one, to lower their history on a cutoff
+        let mut quiets = arrayvec::ArrayVec::<_, 256>::new();
+        let mut captures = arrayvec::ArrayVec::<_, 256>::new();
         let _game = &game;
-        for (i, (m, _)) in moves.iter().copied().enumerate() {
+        while let Some(m) = if ROOT { root_moves.next() } else { picker.next(self, _game, prev_move, p_killer) } {
+            any_legal = true;
+            if m == excluded { continue };
+
             // apply futility pruning if we could and if this move is quiet
             #[cfg(feature = "search-fp")]
             if can_f_prune && children_searched > 0 && _game.is_quiet(m) {
@@ -300,13 +435,49 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
                 continue;
             }
 
+            let mut extension = 0;
+
+            // singular extensions: extend the TT move if all the others fail low against a
+            // margin below its evaluation
+            #[cfg(feature = "search-se")]
+            if let Some(tte) = tte.filter(|tte| {
+                !ROOT && !singular_search && depth >= 8 && tte.next == m
+                    && tte.depth as usize + 3 >= depth
+                    && matches!(tte.node_type(), NodeType::Cut | NodeType::Pv)
+                    && !tte.eval.is_mate()
+            }) {
+                let tt_eval = Eval::from_tt(tte.eval, ply);
+                let singular_beta = tt_eval - tunables::se_margin() as i16 * depth as i16;
+                let singular_bound = Bound::new(singular_beta - 1, singular_beta);
+
+                let (_, eval, _) = self._evaluate_search::<Cut, false>(prev_move, _game, p_killer, (depth - 1) / 2, ply, singular_bound, true, m);
+
+                if eval < singular_beta {
+                    extension = 1;
+                } else if singular_beta >= bound.beta {
+                    // multi-cut: more than one move beats beta
+                    return (m, singular_beta, NodeType::None);
+                } else if tt_eval >= bound.beta {
+                    extension = -1;
+                }
+            }
+
             let game = _game.make_move(m);
             let line = PrevMove {
                 mov: m,
-                static_eval: EvalCell::new(game.board()),
+                piece: _game.board().piece_on(m.get_source()),
+                static_eval: EvalCell::new(&game),
                 prev_move: Some(prev_move),
             };
 
+            // check extensions, only up to twice the depth so that perpetual checks end
+            #[cfg(feature = "search-check-ext")]
+            if game.board().checkers().0 != 0 && ply < 2 * self.root_depth {
+                extension = extension.max(1);
+            }
+
+            let new_depth = (depth as isize - 1 + extension) as usize;
+
             let can_reduce = cfg!(feature = "search-lmr")
                 && depth >= 3 && !in_check && children_searched != 0;
 
@@ -315,12 +486,19 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
                 let ln_depth = (depth as f32).ln();
                 let ln_nodes = ((children_searched + 1) as f32).ln();
 
-                let mut r = ln_depth * ln_nodes * 0.4 + 2.78;
+                let mut r = ln_depth * ln_nodes * (tunables::lmr_mul() as f32 / 100.0) + tunables::lmr_base() as f32 / 100.0;
                 r -= Node::PV as u8 as f32;
                 r -= line.is_improving() as u8 as f32;
-                let r = (r.round() as usize).max(1).min(depth);
+                // reduce moves that did well before less, and the others more
+                let stat = if _game.is_capture(m) {
+                    self.capture_history(_game, m)
+                } else {
+                    self.continuation_history(_game, prev_move, m)
+                };
+                r -= (stat as f32 / tunables::lmr_hist_div() as f32).clamp(-2.0, 2.0);
+                let r = (r.round() as usize).max(1).min(new_depth + 1);
 
-                eval = -self.zw_search::<Node::Zw>(&line, &game, &killer, depth - r, ply + 1, -bound.alpha);
+                eval = -self.zw_search::<Node::Zw>(&line, &game, &killer, new_depth + 1 - r, ply + 1, -bound.alpha);
 
                 if bound.alpha < eval && r > 1 {
                     self.debug.research.inc();
@@ -334,12 +512,12 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
             };
 
             if do_full_research {
-                eval = -self.zw_search::<Node::Zw>(&line, &game, &killer, depth - 1, ply + 1, -bound.alpha);
+                eval = -self.zw_search::<Node::Zw>(&line, &game, &killer, new_depth, ply + 1, -bound.alpha);
                 self.debug.all_full_zw.inc();
             }
 
             if Node::PV && (children_searched == 0 || bound.alpha < eval) {
-                eval = -self.evaluate_search::<Pv>(&line, &game, &killer, depth - 1, ply + 1, -bound, in_zw);
+                eval = -self.evaluate_search::<Pv>(&line, &game, &killer, new_depth, ply + 1, -bound, in_zw);
 
                 self.debug.all_full.inc();
                 if do_full_research {
@@ -347,8 +525,8 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
                 }
             }
 
-            if self.abort() { return (best.0, best.1.incr_mate(), NodeType::None) };
-            self.nodes_searched += 1;
+            if self.abort() { return (best.0, best.1, NodeType::None) };
+            self.count_node();
 
             // if ROOT {
             //     println!(" {m} {eval} α{alpha} β{beta} {:?}", self.find_pv(m, 100).into_iter().map(|i| i.to_string()).collect::<Vec<_>>());
@@ -360,38 +538,62 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
             }
 
             if eval >= bound.beta {
-                if !_game.is_capture(m) {
-                    let bonus = 300 * depth as isize - 250;
+                let bonus = tunables::hist_bonus_mul() as isize * depth as isize - tunables::hist_bonus_base() as isize;
 
-                    for (m, _) in moves[..i].into_iter() {
-                        if !_game.is_capture(*m) {
-                            self.hist_table.update(*m, -bonus);
-                            p_killer.update(*m, -bonus);
-                        }
+                if !_game.is_capture(m) {
+                    for m in quiets.iter() {
+                        self.hist_table.update(*m, -bonus);
+                        p_killer.update(*m, -bonus);
+                        self.update_continuation_history(_game, prev_move, *m, -bonus);
                     }
 
                     self.hist_table.update(m, bonus);
                     p_killer.update(m, bonus);
+                    p_killer.push(m);
+                    self.update_continuation_history(_game, prev_move, m, bonus);
 
                     *self.countermove.get_mut(prev_move.mov) = m;
+                } else if cfg!(feature = "moveord-capt-hist") {
+                    self.capt_hist.update(_game, m, bonus);
+                }
+
+                if cfg!(feature = "moveord-capt-hist") {
+                    for m in captures.iter() {
+                        self.capt_hist.update(_game, *m, -bonus);
+                    }
                 }
 
-                return (best.0, best.1.incr_mate(), NodeType::Cut);
+                return (best.0, best.1, NodeType::Cut);
             }
 
+            if _game.is_capture(m) {
+                captures.push(m);
+            } else {
+                quiets.push(m);
+            }
             children_searched += 1;
         }
 
-        (best.0, best.1.incr_mate(), if best.1 == bound.alpha { NodeType::All } else { NodeType::Pv })
+        if !any_legal {
+            return (ChessMove::default(), if in_check { Eval::mated_in(ply) } else { Eval(0) }, NodeType::None);
+        }
+
+        (best.0, best.1, if best.1 == bound.alpha { NodeType::All } else { NodeType::Pv })
     }
 
     #[inline]
-    fn quiescence_search(&mut self, game: &Game, bound: Bound) -> Eval {
-        self._quiescence_search(game, bound).0
+    fn quiescence_search(&mut self, game: &Game, ply: usize, bound: Bound) -> Eval {
+        self._quiescence_search(game, ply, bound).0
     }
 
-    fn _quiescence_search(&mut self, game: &Game, mut bound: Bound) -> (Eval, NodeType) {
+    fn _quiescence_search(&mut self, game: &Game, ply: usize, mut bound: Bound) -> (Eval, NodeType) {
         let in_check = game.board().checkers().0 != 0;
+        let moves = MoveGen::new_legal(game.board());
+
+        // mates and stalemates have to be found before standing pat
+        if moves.len() == 0 {
+            return (if in_check { Eval::mated_in(ply) } else { Eval(0) }, NodeType::None);
+        }
 
         let mut best;
         let standing_pat;
@@ -400,7 +602,7 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
             standing_pat = Eval::MIN;
             best = Eval::MIN;
         } else {
-            standing_pat = evaluate_static(game.board());
+            standing_pat = evaluate_static(game);
             // TODO: failing to standing pat makes sprt fail, need investigation
             if standing_pat >= bound.beta { return (bound.beta, NodeType::Cut) };
             best = standing_pat;
@@ -414,8 +616,6 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
             bound.alpha = bound.alpha.max(standing_pat);
         }
 
-        let moves = MoveGen::new_legal(game.board());
-
         for m in moves {
             if !in_check {
                 if game.is_quiet(m) { continue };
@@ -430,8 +630,8 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
             if see(game, m) < 0 { continue };
 
             let game = game.make_move(m);
-            let eval = -self.quiescence_search(&game, -bound);
-            self.nodes_searched += 1;
+            let eval = -self.quiescence_search(&game, ply + 1, -bound);
+            self.count_node();
 
             if eval > best {
                 best = eval;
@@ -445,3 +645,14 @@ impl<const MAIN: bool> SmpThread<'_, MAIN> {
         (best, if best == bound.alpha { NodeType::All } else { NodeType::Pv })
     }
 }
+
+#[test]
+fn test_check_extension_limit() {
+    // black escapes the checks, but white has them on every move
+    let mut engine = Engine::new(Game::from_fen("6k1/6p1/6Q1/8/8/8/r4qPP/7K w - - 0 1").unwrap(), 1 << 20);
+    engine.allow_for(Duration::MAX);
+    engine.set_limits(SearchLimits { depth: Some(12), ..Default::default() });
+    engine.best_move(|_, _| true);
+
+    assert!(engine.nodes() < 1_000_000, "{} nodes", engine.nodes());
+}
@@ -1,83 +1,190 @@
-use core::cell::UnsafeCell;
-use bytemuck::*;
-use fxhash::hash64;
+use core::{alloc::Layout, marker::PhantomData, ptr::NonNull, sync::atomic::{AtomicU64, Ordering}};
+use std::alloc;
 
-pub struct SharedHashTable<T: Clone + Sized + Send + Sync + NoUninit> {
-    inner: Box<[UnsafeCell<TableEntry<T>>]>,
+/// Values that can be stored in a [`SharedHashTable`] by packing them into 64 bits
+pub trait Packed {
+    fn pack(&self) -> u64;
+    fn unpack(bits: u64) -> Self;
 }
 
-#[repr(packed)]
-#[derive(Default, Clone)]
-pub struct TableEntry<T: Clone + Sized + Send + Sync + NoUninit> {
-    key: u64,
-    hash: u64,
-    value: T,
+impl Packed for u64 {
+    fn pack(&self) -> u64 { *self }
+    fn unpack(bits: u64) -> Self { bits }
 }
 
-unsafe impl<T: Default + Clone + Sized + Send + Sync + NoUninit> Sync for SharedHashTable<T> {}
+/// Hash table shared between threads without locking, where every key maps to a bucket of `N`
+/// entries sharing a cache line. Which entry of a bucket gets replaced is up to the user.
+///
+/// Entries are stored as `key ^ data` and `data`, so an entry torn by concurrent writes decodes
+/// to a different key and is never found.
+pub struct SharedHashTable<T: Packed, const N: usize> {
+    ptr: NonNull<Bucket<N>>,
+    len: usize,
+    _value: PhantomData<T>,
+}
+
+struct TableEntry {
+    key_xor_data: AtomicU64,
+    data: AtomicU64,
+}
+
+#[repr(C, align(64))]
+struct Bucket<const N: usize>([TableEntry; N]);
 
-impl<T: Default + Clone + Sized + Send + Sync + NoUninit> SharedHashTable<T> {
-    pub const fn entry_size() -> usize { core::mem::size_of::<TableEntry<T>>() }
+// SAFETY: the table only hands out atomics
+unsafe impl<T: Packed, const N: usize> Send for SharedHashTable<T, N> {}
+unsafe impl<T: Packed, const N: usize> Sync for SharedHashTable<T, N> {}
 
-    pub fn new(size: usize) -> Self {
-        let mut inner = vec![];
-        inner.resize_with(size, UnsafeCell::default);
+const HUGE_PAGE: usize = 2 * 1024 * 1024;
 
-        Self { inner: inner.into() }
+impl<T: Packed, const N: usize> SharedHashTable<T, N> {
+    pub const fn bucket_size() -> usize { core::mem::size_of::<Bucket<N>>() }
+
+    /// Allocate a table of `buckets` buckets, cleared with `threads` threads
+    pub fn new(buckets: usize, threads: usize) -> Self {
+        let len = buckets.max(1);
+        let layout = Self::layout(len);
+
+        // SAFETY: the layout always has at least one bucket
+        let ptr = unsafe { alloc::alloc(layout) };
+        let Some(ptr) = NonNull::new(ptr.cast::<Bucket<N>>()) else { alloc::handle_alloc_error(layout) };
+
+        // large tables are aligned to huge pages, so ask for them to cut down on TLB misses
+        #[cfg(target_os = "linux")]
+        if layout.align() == HUGE_PAGE {
+            // SAFETY: the range is exactly the allocation, and this is only a hint
+            unsafe { libc::madvise(ptr.as_ptr().cast(), layout.size(), libc::MADV_HUGEPAGE) };
+        }
+
+        let mut table = Self { ptr, len, _value: PhantomData };
+        table.clear(threads);
+        table
     }
 
-    pub fn clear(&mut self) {
-        for e in self.inner.iter_mut() {
-            unsafe { (*e.get()).hash = 0 };
+    fn layout(len: usize) -> Layout {
+        let layout = Layout::array::<Bucket<N>>(len).unwrap();
+
+        if layout.size() >= HUGE_PAGE {
+            layout.align_to(HUGE_PAGE).unwrap()
+        } else {
+            layout
         }
     }
 
-    pub fn insert(&self, key: u64, value: T) {
-        let hash = hash64(&(key, bytemuck::bytes_of(&value)));
-        let entry = TableEntry { key, hash, value };
-        unsafe { *self.inner[key as usize % self.inner.len()].get() = entry; }
+    /// Zero every bucket, split across `threads` scoped threads spawned just for this. These aren't
+    /// the search threads, so it only makes clearing a large table faster.
+    pub fn clear(&mut self, threads: usize) {
+        let chunk = self.len.div_ceil(threads.max(1));
+        // raw pointers aren't `Send`, but the parts written are disjoint
+        let base = self.ptr.as_ptr() as usize;
+        let zero = |start: usize| {
+            let len = chunk.min(self.len - start);
+            // SAFETY: all zeros is an empty bucket, and `start..start + len` is in the allocation
+            unsafe { core::ptr::write_bytes((base as *mut Bucket<N>).add(start), 0, len) };
+        };
+
+        if threads <= 1 {
+            zero(0);
+            return;
+        }
+
+        std::thread::scope(|s| {
+            for start in (0..self.len).step_by(chunk) {
+                s.spawn(move || zero(start));
+            }
+        });
     }
 
-    pub fn get(&self, key: u64) -> Option<T> {
-        let entry = unsafe { (*self.inner[key as usize % self.inner.len()].get()).clone() };
-        let value = entry.value;
+    #[inline]
+    fn buckets(&self) -> &[Bucket<N>] {
+        // SAFETY: the buckets are initialized on creation
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
 
-        (entry.key == key && entry.hash == hash64(&(key, bytemuck::bytes_of(&value)))).then_some(value)
+    #[inline]
+    fn index_of(&self, key: u64) -> usize {
+        key as usize % self.len
     }
 
-    pub fn get_place(&self, key: u64) -> Option<T> {
-        let entry = unsafe { (*self.inner[key as usize % self.inner.len()].get()).clone() };
-        let value = entry.value;
+    /// Key and value of entry `slot` in bucket `index`, or `None` if it is empty or torn
+    #[inline]
+    fn read(&self, index: usize, slot: usize) -> Option<(u64, T)> {
+        let entry = &self.buckets()[index].0[slot];
+        let key_xor_data = entry.key_xor_data.load(Ordering::Relaxed);
+        let data = entry.data.load(Ordering::Relaxed);
+        let key = key_xor_data ^ data;
 
-        (entry.hash == hash64(&(key, bytemuck::bytes_of(&value)))).then_some(value)
+        ((key_xor_data, data) != (0, 0) && self.index_of(key) == index).then(|| (key, T::unpack(data)))
     }
 
-    pub fn filter_count<F: Fn(T) -> bool>(&self, filter: F) -> usize {
-        self.inner.iter().filter(|entry| {
-            let entry = unsafe { (*entry.get()).clone() };
-            let value = entry.value;
+    /// Write `value` into entry `slot` of the bucket of `key`
+    pub fn store(&self, key: u64, slot: usize, value: T) {
+        let data = value.pack();
+        let entry = &self.buckets()[self.index_of(key)].0[slot];
 
-            entry.hash == hash64(&bytemuck::bytes_of(&value)) && filter(value)
-        }).count()
+        entry.data.store(data, Ordering::Relaxed);
+        entry.key_xor_data.store(key ^ data, Ordering::Relaxed);
     }
 
-    pub fn size(&self) -> usize { self.inner.len() }
+    pub fn get(&self, key: u64) -> Option<T> {
+        let index = self.index_of(key);
+
+        (0..N).find_map(|slot| self.read(index, slot).filter(|(k, _)| *k == key)).map(|(_, value)| value)
+    }
+
+    /// Every entry in the bucket of `key` with the key it was stored with, or `None` if empty
+    pub fn bucket(&self, key: u64) -> [Option<(u64, T)>; N] {
+        let index = self.index_of(key);
+        core::array::from_fn(|slot| self.read(index, slot))
+    }
+
+    pub fn filter_count<F: Fn(T) -> bool>(&self, filter: F) -> usize {
+        (0..self.len)
+            .flat_map(|index| (0..N).map(move |slot| (index, slot)))
+            .filter(|(index, slot)| self.read(*index, *slot).is_some_and(|(_, value)| filter(value)))
+            .count()
+    }
+
+    /// Number of entries
+    pub fn size(&self) -> usize { self.len * N }
+}
+
+impl<T: Packed, const N: usize> Drop for SharedHashTable<T, N> {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `new` with the same layout
+        unsafe { alloc::dealloc(self.ptr.as_ptr().cast(), Self::layout(self.len)) };
+    }
 }
 
 #[test]
 fn test_shared_table() {
-    let st = std::sync::Arc::new(SharedHashTable::<usize>::new(10));
+    let st = std::sync::Arc::new(SharedHashTable::<u64, 1>::new(10, 1));
 
-    st.insert(0, 123);
+    st.store(0, 0, 123);
     assert_eq!(st.get(0), Some(123));
-    st.insert(10, 123);
+    st.store(10, 0, 123);
     assert_eq!(st.get(10), Some(123));
     assert_eq!(st.get(0), None);
 
     {
         let st = std::sync::Arc::clone(&st);
-        std::thread::spawn(move || st.insert(1, 789)).join().unwrap();
+        std::thread::spawn(move || st.store(1, 0, 789)).join().unwrap();
     }
 
     assert_eq!(st.get(1), Some(789));
+    assert_eq!(st.filter_count(|_| true), 2);
+
+    let st = SharedHashTable::<u64, 2>::new(10, 2);
+
+    st.store(0, 0, 123);
+    st.store(10, 1, 456);
+    assert_eq!(st.get(0), Some(123));
+    assert_eq!(st.get(10), Some(456));
+    assert_eq!(st.bucket(20), [Some((0, 123)), Some((10, 456))]);
+
+    // a torn entry, with the data of one store and the key of another
+    st.store(11, 0, 789);
+    st.buckets()[1].0[0].data.store(123, Ordering::Relaxed);
+    assert_eq!(st.get(11), None);
+    assert_eq!(st.filter_count(|_| true), 2);
 }
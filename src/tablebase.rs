@@ -0,0 +1,98 @@
+//! Syzygy endgame tablebase probing, backed by `shakmaty-syzygy`.
+
+use chess::{CastleRights, ChessMove, Color, MoveGen};
+use shakmaty::{Bitboard, CastlingMode, Chess, FromSetup, Piece, Role, Setup, Square};
+use shakmaty_syzygy::{Tablebase, Wdl};
+
+use crate::{Eval, Game};
+
+pub struct Syzygy {
+    tables: Tablebase<Chess>,
+}
+
+impl Syzygy {
+    /// Load the tables of every directory in `paths`, which are separated like in `PATH`
+    pub fn open(paths: &str) -> std::io::Result<Self> {
+        let mut tables = Tablebase::new();
+
+        for dir in std::env::split_paths(paths) {
+            tables.add_directory(dir)?;
+        }
+
+        Ok(Self { tables })
+    }
+
+    pub fn max_pieces(&self) -> usize { self.tables.max_pieces() }
+
+    pub fn can_probe(&self, game: &Game) -> bool {
+        game.board().combined().popcnt() as usize <= self.max_pieces()
+    }
+
+    /// Convert straight from the bitboards, since this happens on every probe in the search
+    fn position(game: &Game) -> Option<Chess> {
+        let board = game.board();
+        let square = |sq: chess::Square| Square::new(sq.to_index() as u32);
+        let mut setup = Setup::empty();
+
+        for sq in board.combined().into_iter() {
+            let role = Role::ALL[board.piece_on(sq)?.to_index()];
+            let color = shakmaty::Color::from_white(board.color_on(sq)? == Color::White);
+            setup.board.set_piece_at(square(sq), Piece { color, role });
+        }
+
+        for (color, rank) in [(Color::White, chess::Rank::First), (Color::Black, chess::Rank::Eighth)] {
+            let rooks = match board.castle_rights(color) {
+                CastleRights::NoRights => &[][..],
+                CastleRights::KingSide => &[chess::File::H][..],
+                CastleRights::QueenSide => &[chess::File::A][..],
+                CastleRights::Both => &[chess::File::A, chess::File::H][..],
+            };
+
+            for file in rooks {
+                setup.castling_rights |= Bitboard::from(square(chess::Square::make_square(rank, *file)));
+            }
+        }
+
+        // the chess crate gives the square of the pawn that can be taken, not the one behind it
+        setup.ep_square = board.en_passant().map(|sq| square(sq.uforward(board.side_to_move())));
+        setup.turn = shakmaty::Color::from_white(board.side_to_move() == Color::White);
+        setup.halfmoves = game.fifty_move_counter() as u32;
+        setup.fullmoves = core::num::NonZeroU32::new(game.fullmove_number() as u32)?;
+
+        Chess::from_setup(setup, CastlingMode::Standard).ok()
+    }
+
+    /// Win, draw or loss of the side to move. Only exact right after a capture or pawn move,
+    /// since the fifty move rule isn't taken into account otherwise.
+    pub fn probe_wdl(&self, game: &Game) -> Option<Eval> {
+        let wdl = self.tables.probe_wdl_after_zeroing(&Self::position(game)?).ok()?;
+
+        Some(match wdl {
+            Wdl::Win => Eval::TB_WIN,
+            Wdl::Loss => -Eval::TB_WIN,
+            _ => Eval(0),
+        })
+    }
+
+    /// The root move keeping the best outcome that also zeroes the fifty move counter soonest,
+    /// and the outcome itself
+    pub fn probe_root(&self, game: &Game) -> Option<(ChessMove, Eval)> {
+        let pos = Self::position(game)?;
+        let dtz = self.tables.probe_dtz(&pos).ok()?.ignore_rounding().0;
+        let (best, _) = self.tables.best_move(&pos).ok()??;
+
+        let uci = best.to_uci(CastlingMode::Standard).to_string();
+        let best = MoveGen::new_legal(game.board()).find(|m| m.to_string() == uci)?;
+
+        // wins and losses that take too long are draws by the fifty move rule
+        let eval = if dtz == 0 || dtz.unsigned_abs() as usize + game.fifty_move_counter() > 100 {
+            Eval(0)
+        } else if dtz > 0 {
+            Eval::TB_WIN
+        } else {
+            -Eval::TB_WIN
+        };
+
+        Some((best, eval))
+    }
+}
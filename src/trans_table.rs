@@ -1,14 +1,25 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
 use crate::{eval::Eval, node::NodeType, shared_table::*};
 
-pub type TransTable = SharedHashTable<TransTableEntry>;
+/// Entries per bucket, as many as fit in a cache line
+const BUCKET_ENTRIES: usize = 4;
+/// Ages wrap around after this many searches
+const AGE_CYCLE: u8 = 1 << 6;
+
+pub struct TransTable {
+    table: SharedHashTable<TransTableEntry, BUCKET_ENTRIES>,
+    /// Generation of the current search, stored in new entries so that ones from earlier searches
+    /// get replaced first
+    age: AtomicU8,
+}
 
-#[repr(packed)]
 #[derive(Default, Clone, Copy)]
 pub struct TransTableEntry {
     pub depth: u8,
     pub eval: Eval,
     pub next: chess::ChessMove,
-    /// 2-bit node type
+    /// 2-bit node type, then 6-bit age
     pub flags: u8,
 }
 
@@ -25,6 +36,117 @@ impl TransTableEntry {
             _ => NodeType::None,
         }
     }
+
+    pub fn age(&self) -> u8 {
+        self.flags >> 2
+    }
+
+    /// How much the entry is worth keeping during the search of generation `age`
+    fn worth(&self, age: u8) -> i32 {
+        let age_distance = (AGE_CYCLE + age - self.age()) % AGE_CYCLE;
+        self.depth as i32 - 8 * age_distance as i32 + 2 * (self.node_type() == NodeType::Pv) as i32
+    }
+}
+
+/// Packed as 8 bits of depth, 16 bits of evaluation, 16 bits of move and 8 bits of flags
+impl Packed for TransTableEntry {
+    fn pack(&self) -> u64 {
+        let promotion = self.next.get_promotion().map_or(0, |p| p.to_index() + 1);
+        let next = self.next.get_source().to_index() | self.next.get_dest().to_index() << 6 | promotion << 12;
+
+        self.depth as u64 | (self.eval.0 as u16 as u64) << 8 | (next as u64) << 24 | (self.flags as u64) << 40
+    }
+
+    fn unpack(bits: u64) -> Self {
+        let next = (bits >> 24) as u16;
+        let promotion = (next >> 12) as usize;
+
+        Self {
+            depth: bits as u8,
+            eval: Eval((bits >> 8) as u16 as i16),
+            next: chess::ChessMove::new(
+                chess::Square::new(next as u8 & 63),
+                chess::Square::new((next >> 6) as u8 & 63),
+                (promotion != 0).then(|| chess::ALL_PIECES[(promotion - 1) % 6]),
+            ),
+            flags: (bits >> 40) as u8,
+        }
+    }
+}
+
+impl TransTable {
+    /// Allocate a table of about `size_bytes` bytes, cleared with `threads` threads
+    pub fn new(size_bytes: usize, threads: usize) -> Self {
+        let buckets = size_bytes / SharedHashTable::<TransTableEntry, BUCKET_ENTRIES>::bucket_size();
+        Self { table: SharedHashTable::new(buckets, threads), age: AtomicU8::new(0) }
+    }
+
+    /// Start a new generation, called at the start of every search
+    pub fn new_search(&self) {
+        let age = self.age.load(Ordering::Relaxed);
+        self.age.store((age + 1) % AGE_CYCLE, Ordering::Relaxed);
+    }
+
+    pub fn age(&self) -> u8 { self.age.load(Ordering::Relaxed) }
+
+    pub fn get(&self, key: u64) -> Option<TransTableEntry> {
+        self.table.get(key)
+    }
+
+    /// Store `entry`, overwriting the entry of the same position unless that one is from this
+    /// search and much deeper, or otherwise the least worth one in the bucket
+    pub fn insert(&self, key: u64, entry: TransTableEntry) {
+        let age = self.age();
+        let entry = TransTableEntry { flags: entry.flags & 3 | age << 2, ..entry };
+        let bucket = self.table.bucket(key);
+
+        if let Some((slot, old)) = bucket.iter().enumerate().find_map(|(i, e)| e.filter(|(k, _)| *k == key).map(|(_, old)| (i, old))) {
+            if entry.node_type() == NodeType::Pv || old.age() != age || entry.depth as usize + 2 >= old.depth as usize {
+                // keep the known best move if the new search didn't find one
+                let next = if entry.next == chess::ChessMove::default() { old.next } else { entry.next };
+                self.table.store(key, slot, TransTableEntry { next, ..entry });
+            }
+
+            return;
+        }
+
+        let slot = bucket.iter()
+            .map(|e| e.map_or(i32::MIN, |(_, old)| old.worth(age)))
+            .enumerate()
+            .min_by_key(|(_, worth)| *worth)
+            .map_or(0, |(slot, _)| slot);
+        self.table.store(key, slot, entry);
+    }
+
+    pub fn filter_count<F: Fn(TransTableEntry) -> bool>(&self, filter: F) -> usize {
+        self.table.filter_count(filter)
+    }
+
+    pub fn size(&self) -> usize { self.table.size() }
+
+    pub fn clear(&mut self, threads: usize) {
+        self.table.clear(threads);
+        *self.age.get_mut() = 0;
+    }
 }
 
-unsafe impl bytemuck::NoUninit for TransTableEntry {}
+#[test]
+fn test_pack() {
+    use chess::{ChessMove, Piece, Square};
+
+    for (next, eval) in [
+        (ChessMove::new(Square::new(52), Square::new(60), Some(Piece::Queen)), 123),
+        (ChessMove::new(Square::new(0), Square::new(63), None), -32000),
+        (ChessMove::new(Square::new(9), Square::new(1), Some(Piece::Knight)), 0),
+    ] {
+        let entry = TransTableEntry {
+            depth: 200,
+            eval: Eval(eval),
+            next,
+            flags: TransTableEntry::new_flags(NodeType::Cut) | 63 << 2,
+        };
+        let unpacked = TransTableEntry::unpack(entry.pack());
+
+        assert_eq!((unpacked.depth, unpacked.eval, unpacked.next, unpacked.flags), (entry.depth, entry.eval, entry.next, entry.flags));
+    }
+}
@@ -0,0 +1,99 @@
+//! Search parameters that can be tuned with SPSA. They are constants unless the `tune` feature is
+//! enabled, in which case they can be changed at runtime through [`set_tunable`].
+
+#[cfg(feature = "tune")]
+use core::sync::atomic::{AtomicI32, Ordering};
+
+/// A tunable parameter with the range and step size to tune it with
+#[derive(Debug, Clone, Copy)]
+pub struct Tunable {
+    pub name: &'static str,
+    pub default: i32,
+    pub min: i32,
+    pub max: i32,
+    pub step: i32,
+}
+
+/// Learning rate for SPSA at the end of tuning, the same for every parameter
+const SPSA_R_END: f64 = 0.002;
+
+impl core::fmt::Display for Tunable {
+    /// Write in the OpenBench SPSA input format
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}, int, {}, {}, {}, {}, {SPSA_R_END}", self.name, self.default, self.min, self.max, self.step)
+    }
+}
+
+macro_rules! tunables {
+    ($($name:ident = $default:literal, $min:literal..=$max:literal, $step:literal;)*) => {
+        pub const TUNABLES: &[Tunable] = &[
+            $(Tunable { name: stringify!($name), default: $default, min: $min, max: $max, step: $step },)*
+        ];
+
+        #[cfg(feature = "tune")]
+        #[allow(non_camel_case_types)]
+        enum Index { $($name,)* }
+
+        #[cfg(feature = "tune")]
+        static VALUES: [AtomicI32; TUNABLES.len()] = [$(AtomicI32::new($default),)*];
+
+        $(
+            #[cfg(not(feature = "tune"))]
+            #[inline(always)]
+            pub(crate) const fn $name() -> i32 { $default }
+
+            #[cfg(feature = "tune")]
+            #[inline(always)]
+            pub(crate) fn $name() -> i32 { VALUES[Index::$name as usize].load(Ordering::Relaxed) }
+        )*
+    };
+}
+
+tunables! {
+    // reverse futility pruning margin per ply
+    rfp_margin = 120, 40..=250, 10;
+    // futility pruning margin per ply
+    fp_margin = 150, 50..=300, 12;
+    // late move reductions are `lmr_base + ln(depth) * ln(moves) * lmr_mul`, in hundredths
+    lmr_base = 278, 100..=400, 15;
+    lmr_mul = 40, 15..=80, 4;
+    // history that takes a ply off or adds a ply to late move reductions
+    lmr_hist_div = 16384, 4096..=32768, 1024;
+    // initial half width of the root aspiration window
+    asp_delta = 13, 5..=40, 2;
+    // null move reduction is `nmp_base + depth / nmp_div`
+    nmp_base = 3, 1..=6, 1;
+    nmp_div = 3, 1..=6, 1;
+    // margin above beta that captures have to beat for probcut
+    probcut_margin = 200, 80..=400, 15;
+    // quiet moves searched before late move pruning are `lmp_base + lmp_mul * depth^2`
+    lmp_base = 4, 0..=12, 1;
+    lmp_mul = 2, 1..=6, 1;
+    // singular extension margin below the TT evaluation per ply
+    se_margin = 2, 1..=6, 1;
+    // history bonus of a cutoff is `hist_bonus_mul * depth - hist_bonus_base`
+    hist_bonus_mul = 300, 100..=600, 25;
+    hist_bonus_base = 250, 0..=600, 25;
+}
+
+/// Set a tunable by its name. Returns `false` if there is none by that name or if the value is
+/// out of its range.
+#[cfg(feature = "tune")]
+pub fn set_tunable(name: &str, value: i32) -> bool {
+    let Some(i) = TUNABLES.iter().position(|t| t.name.eq_ignore_ascii_case(name)) else { return false };
+    if !(TUNABLES[i].min..=TUNABLES[i].max).contains(&value) { return false };
+
+    VALUES[i].store(value, Ordering::Relaxed);
+    true
+}
+
+#[test]
+fn test_tunables() {
+    for (i, t) in TUNABLES.iter().enumerate() {
+        assert!(t.min <= t.default && t.default <= t.max, "{} out of range", t.name);
+        assert!(TUNABLES[..i].iter().all(|o| o.name != t.name), "{} repeated", t.name);
+    }
+
+    assert_eq!(rfp_margin(), 120);
+    assert_eq!(TUNABLES[0].to_string(), "rfp_margin, int, 120, 40, 250, 10, 0.002");
+}